@@ -4,7 +4,15 @@ use crate::{
         Collection,
         CollectionError,
         collection_router,
+        lookup_router,
+        admin_router,
         SharedCollection,
+        NodeClassification,
+        Attrs,
+        AttrsReport,
+        LookupMode,
+        Visibility,
+        reload,
     },
 };
 use tracing::{Level, Span};
@@ -34,16 +42,29 @@ pub async fn run_server(args: &config::CliArgs) -> Result<(), ApiError> {
     };
     tracing::info!("loading collection from {:?} ...", &args.collection_dir);
     let start_time = Instant::now();
-    let collections = Collection::try_from((&args.collection_dir, args.ignore_bad_documents))
-        .map_err(|e| ApiError::from(e))?;
+    let collections = Collection::try_from((&args.collection_dir, &args.collection_options()))
+        .map_err(ApiError::from)?;
     tracing::info!(
-        "loaded {} documents from {} collections in {:?}",
-        collections.total_documents(), collections.total_collections(), &start_time.elapsed()
+        "loaded {} documents from {} collections in {:?}, {} files at {:.0} files/s with {} threads",
+        collections.total_documents(), collections.total_collections(), &start_time.elapsed(),
+        collections.report.total_files, collections.report.files_per_sec(), collections.report.threads
     );
     let (total_collections, total_documents) = (collections.total_collections(), collections.total_documents());
-    let collections = SharedCollection::from(collections);
+    let collections = SharedCollection::from((collections, args.api_settings()));
+    let _watcher = match args.watch {
+        true => Some(reload::watch(
+            collections.clone(),
+            args.collection_dir.clone(),
+            Duration::from_millis(args.watch_delay),
+        ).map_err(ApiError::WatchError)?),
+        false => None,
+    };
+    #[cfg(unix)]
+    reload::reload_on_hangup(collections.clone()).map_err(ApiError::SignalError)?;
     let app = Router::new()
         .nest("/collection", collection_router())
+        .merge(lookup_router())
+        .nest("/admin", admin_router())
         .layer(log_service())
         .layer(CompressionLayer::new())
         .with_state(collections);
@@ -57,6 +78,28 @@ pub async fn run_server(args: &config::CliArgs) -> Result<(), ApiError> {
     Ok(())
 }
 
+///
+/// Print a Puppet External Node Classifier document for a host with name `fqdn`
+/// and attributes given by `--attr` options.
+/// Hidden values are printed as is, as they are needed to configure the host.
+/// With `--strict-lookup` it fails if the host misses attributes used by overrides.
+///
+pub fn print_node_classification(args: &config::CliArgs, fqdn: &str) -> Result<(), ApiError> {
+    let collections = Collection::try_from((&args.collection_dir, &args.collection_options()))
+        .map_err(ApiError::from)?;
+    let mut attrs = Attrs::from(args.attrs.iter().cloned().collect::<std::collections::HashMap<String, String>>());
+    attrs.insert("fqdn", fqdn);
+    attrs.validate().map_err(CollectionError::AttrsError)?;
+    let attrs = collections.resolve_attrs(&attrs)?;
+    if args.strict_lookup {
+        LookupMode::Strict.check(collections.find_documents(None, None), &attrs).map_err(ApiError::MissingAttrs)?;
+    }
+    let node = NodeClassification::try_from((&collections, &attrs, Visibility::Revealed))?;
+    let yaml = node.to_yaml().map_err(ApiError::EncError)?;
+    print!("{}", yaml);
+    Ok(())
+}
+
 async fn hello() -> Html<&'static str> {
     Html("Hello, world")
 }
@@ -64,6 +107,10 @@ async fn hello() -> Html<&'static str> {
 #[derive(Debug)]
 pub enum ApiError {
     CollectionError(CollectionError),
+    EncError(serde_yaml::Error),
+    MissingAttrs(AttrsReport),
+    WatchError(notify::Error),
+    SignalError(std::io::Error),
 }
 
 impl From<CollectionError> for ApiError {
@@ -0,0 +1,212 @@
+///
+///
+/// The module provides attributes used to look up values.
+///
+/// An attribute may have many values, i.e. a host may be a member of several groups.
+/// Attributes are given either as a query `?fqdn=host.example.com&domain=example.com`
+/// or as a JSON object with strings, numbers, booleans and lists of them
+/// `{"fqdn": "host.example.com", "is_virtual": true, "groups": ["web", "db"]}`.
+///
+///
+use serde::{Deserialize, Serialize, Serializer, Deserializer};
+use std::collections::HashMap;
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::{FromRequest, Query, Json},
+    http::{Request, Method, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError,
+};
+
+/// Characters used to build compare keys, they are not allowed in attributes.
+pub const DELIMITERS: [char; 2] = [',', '='];
+
+/// The most values an attribute may have in a lookup.
+pub const MAX_VALUES: usize = 32;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Attrs(HashMap<String, Vec<String>>);
+
+impl Attrs {
+    ///
+    /// Get values of an attribute.
+    ///
+    pub fn get(&self, attr: &str) -> Option<&Vec<String>> {
+        self.0.get(attr)
+    }
+
+    ///
+    /// Get values of an attribute or a single empty value if the attribute is missing.
+    ///
+    pub fn values(&self, attr: &str) -> Vec<&str> {
+        match self.0.get(attr) {
+            Some(values) if ! values.is_empty() => values.iter().map(|it| it.as_str()).collect(),
+            _ => vec![""],
+        }
+    }
+
+    ///
+    /// Set a single value of an attribute.
+    ///
+    pub fn insert(&mut self, attr: &str, value: &str) {
+        self.0.insert(attr.trim().to_lowercase(), vec![value.trim().to_string()]);
+    }
+
+    ///
+    /// Set many values of an attribute.
+    ///
+    pub fn insert_values(&mut self, attr: &str, values: Vec<String>) {
+        self.0.insert(attr.trim().to_lowercase(), values);
+    }
+
+    pub fn remove(&mut self, attr: &str) -> Option<Vec<String>> {
+        self.0.remove(attr)
+    }
+
+    pub fn contains(&self, attr: &str) -> bool {
+        self.0.contains_key(attr)
+    }
+
+    pub fn names(&self) -> Vec<&String> {
+        self.0.keys().collect()
+    }
+
+    ///
+    /// Replace every value of every attribute with a result of `f`.
+    ///
+    pub fn map_values<F: Fn(&str) -> String>(&mut self, f: F) {
+        self.0.values_mut().flatten().for_each(|value| *value = f(value));
+    }
+
+    ///
+    /// Check attributes do not contain delimiters which would corrupt compare keys
+    /// and do not have more than `MAX_VALUES` values.
+    ///
+    pub fn validate(&self) -> Result<(), String> {
+        for (attr, values) in self.0.iter() {
+            if attr.is_empty() || attr.contains(DELIMITERS) {
+                return Err(format!("bad attribute name {:?}", attr));
+            }
+            if values.len() > MAX_VALUES {
+                return Err(format!("attribute {} has {} values, at most {} are allowed", attr, values.len(), MAX_VALUES));
+            }
+            if let Some(value) = values.iter().find(|it| it.contains(DELIMITERS)) {
+                return Err(format!("attribute {} has a value {:?} with one of {:?}", attr, value, DELIMITERS));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<HashMap<String, String>> for Attrs {
+    fn from(attrs: HashMap<String, String>) -> Self {
+        Self::from(&attrs)
+    }
+}
+
+impl From<&HashMap<String, String>> for Attrs {
+    fn from(attrs: &HashMap<String, String>) -> Self {
+        let mut this = Self::default();
+        attrs.iter().for_each(|(attr, value)| this.insert(attr, value));
+        this
+    }
+}
+
+impl TryFrom<serde_json::Value> for Attrs {
+    type Error = String;
+    ///
+    /// Convert a JSON object into attributes. Numbers and booleans become strings,
+    /// lists become many values and `null` means the attribute is missing.
+    ///
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        let scalar = |attr: &str, value: &serde_json::Value| match value {
+            serde_json::Value::String(s) => Ok(s.trim().to_string()),
+            serde_json::Value::Bool(_) | serde_json::Value::Number(_) => Ok(value.to_string()),
+            _ => Err(format!("attribute {} has a value {} which is not a string, number or boolean", attr, value)),
+        };
+        let object = match value {
+            serde_json::Value::Object(object) => object,
+            value => return Err(format!("attributes {} are not an object", value)),
+        };
+        let mut this = Self::default();
+        for (attr, value) in object.iter() {
+            let values = match value {
+                serde_json::Value::Null => continue,
+                serde_json::Value::Array(items) => items.iter()
+                    .map(|it| scalar(attr, it))
+                    .collect::<Result<Vec<String>, String>>()?,
+                value => vec![scalar(attr, value)?],
+            };
+            this.0.insert(attr.trim().to_lowercase(), values);
+        }
+        this.validate()?;
+        Ok(this)
+    }
+}
+
+impl Serialize for Attrs {
+    ///
+    /// Attributes with a single value are serialized as strings, others as lists.
+    ///
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(untagged)]
+        enum Value<'a> {
+            One(&'a String),
+            Many(&'a Vec<String>),
+        }
+        let items = self.0.iter()
+            .map(|(attr, values)| (attr, match values.len() {
+                1 => Value::One(&values[0]),
+                _ => Value::Many(values),
+            }))
+            .collect::<HashMap<&String, Value>>();
+        items.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Attrs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        Attrs::try_from(value).map_err(serde::de::Error::custom)
+    }
+}
+
+pub struct AttrsRejection(String);
+
+impl IntoResponse for AttrsRejection {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, self.0).into_response()
+    }
+}
+
+#[async_trait]
+impl<S, B> FromRequest<S, B> for Attrs
+where
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = AttrsRejection;
+    ///
+    /// Get attributes from a JSON body of `POST` requests or from a query of other requests.
+    ///
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        match req.method() == Method::POST {
+            true => {
+                let Json(attrs) = Json::<Attrs>::from_request(req, state).await
+                    .map_err(|err| AttrsRejection(err.to_string()))?;
+                Ok(attrs)
+            },
+            false => {
+                let Query(query) = Query::<HashMap<String, String>>::from_request(req, state).await
+                    .map_err(|err| AttrsRejection(err.to_string()))?;
+                let attrs = Attrs::from(query);
+                attrs.validate().map_err(AttrsRejection)?;
+                Ok(attrs)
+            },
+        }
+    }
+}
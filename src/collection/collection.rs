@@ -1,12 +1,26 @@
-use super::{document::{Document, ParamValue, DocumentError}};
-use std::{path, collections::HashMap, convert::TryFrom, fmt, iter};
+use super::{document::{Document, ParamValue, DocumentError, MergeStrategy, MAX_COMPARE_KEYS, count_compare_keys}, attrs::Attrs, pipeline::AttrsPipeline, schema::{AttrSchema, Schema}};
+use std::{
+    path, collections::{HashMap, BTreeMap, BTreeSet}, convert::TryFrom, fmt, iter, io::Read, thread,
+    sync::{mpsc, Mutex}, time::{Duration, Instant, SystemTime},
+};
+use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 
+/// A file in the root of a collection directory with settings of the collection.
+pub const CONFIG_FILE: &str = ".takeit.yml";
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Collection {
     // key is a document module, values are documents are elements of the module
     pub documents: HashMap<String, Vec<Document>>,
+    pub config: CollectionConfig,
+    /// a directory the collection was loaded from
+    pub path: path::PathBuf,
+    /// options the collection was loaded with
+    pub options: CollectionOptions,
+    pub report: LoadReport,
+    /// distinct override orders of documents with enabled overrides, they bound the cost of lookups
+    pub order_levels: BTreeSet<Vec<String>>,
 }
 
 impl Collection {
@@ -15,14 +29,13 @@ impl Collection {
     }
 
     pub fn total_documents(&self) -> usize {
-        self.documents.iter().map(|(_, docs)| docs.len()).sum()
+        self.documents.values().map(|docs| docs.len()).sum()
     }
     ///
     /// Get a document by a collection name (`module`) and document name (`name`).
     ///
     pub fn get_document(&self, collection_name: &String, name: &String) -> Option<&Document> {
-        self.documents.get(collection_name)
-            .map_or(None, |docs| docs.iter().find(|d| &d.name == name))
+        self.documents.get(collection_name).and_then(|docs| docs.iter().find(|d| &d.name == name))
     }
     ///
     /// Get a list of documents by a collection `name`.
@@ -32,52 +45,333 @@ impl Collection {
     }
 
     ///
-    /// Look up values for all documents in the collection with name `collection_name`.
+    /// Get names of all documents as `collection/document` with checksums of their sources.
+    ///
+    pub fn checksums(&self) -> BTreeMap<String, u64> {
+        self.documents.values()
+            .flatten()
+            .map(|doc| (format!("{}/{}", &doc.collection, &doc.name), doc.checksum))
+            .collect()
+    }
+
+    ///
+    /// Get schemas of attributes used by override orders of documents in the collection `name`
+    /// sorted by attribute names.
+    ///
+    pub fn get_attrs(&self, name: &String) -> Option<Vec<AttrSchema>> {
+        let names = self.get_documents(name)?.iter()
+            .flat_map(|doc| doc.override_attrs())
+            .collect::<BTreeSet<String>>();
+        Some(self.config.schema.describe(&names.into_iter().collect::<Vec<String>>()))
+    }
+
+    ///
+    /// Get documents of collections `collection_names` with names `document_names`,
+    /// all collections or documents are taken if `None`. Unknown names are skipped.
     ///
-    pub fn get_values(&self, collection_name: &String, attrs: &HashMap<String, String>) -> Option<HashMap<String, ParamValue>> {
-        match self.get_documents(&collection_name) {
-            Some(documents) => {
-                Some(documents.iter().map(|doc| (doc.name.clone(), doc.get_value(&attrs))).collect())
+    pub fn find_documents(&self, collection_names: Option<&[String]>, document_names: Option<&[String]>) -> Vec<&Document> {
+        self.documents.iter()
+            .filter(|(name, _)| collection_names.is_none_or(|names| names.contains(name)))
+            .flat_map(|(_, docs)| docs.iter())
+            .filter(|doc| document_names.is_none_or(|names| names.contains(&doc.name)))
+            .collect()
+    }
+
+    ///
+    /// Prepare attributes of a request for lookups with the attribute pipeline of the collection
+    /// and check them against the schema. Unknown or malformed attributes are rejected
+    /// with `CollectionError::AttrsError`, so are attributes with so many values that an override
+    /// order would expand into more than `MAX_COMPARE_KEYS` compare keys.
+    ///
+    pub fn resolve_attrs(&self, attrs: &Attrs) -> Result<Attrs, CollectionError> {
+        let attrs = self.config.pipeline.apply(attrs);
+        attrs.validate().map_err(CollectionError::AttrsError)?;
+        self.config.schema.validate_attrs(&attrs).map_err(CollectionError::AttrsError)?;
+        for level in self.order_levels.iter() {
+            let total = count_compare_keys(&attrs, level, &self.options.hierarchical_attrs);
+            if total > MAX_COMPARE_KEYS {
+                return Err(CollectionError::AttrsError(format!(
+                    "attributes expand override order {} into {} compare keys, at most {} are allowed",
+                    level.join(","), total, MAX_COMPARE_KEYS
+                )));
             }
-            _ => None,
+        }
+        Ok(attrs)
+    }
+
+    ///
+    /// Look up values for all documents in the collection with name `collection_name`.
+    /// Documents which values are omitted are left out.
+    ///
+    pub fn get_values(&self, collection_name: &String, attrs: &Attrs) -> Option<HashMap<String, ParamValue>> {
+        self.get_documents(collection_name).map(|documents| {
+            documents.iter()
+                .filter_map(|doc| doc.get_value(attrs).map(|value| (doc.name.clone(), value)))
+                .collect()
+        })
+    }
+
+    ///
+    /// Look up values for documents with names `document_names` in the collection with name `collection_name`.
+    /// Unknown documents and documents which values are omitted are left out.
+    ///
+    pub fn get_document_values(&self, collection_name: &String, document_names: &[String], attrs: &Attrs)
+        -> Option<HashMap<String, ParamValue>>
+    {
+        self.get_documents(collection_name).map(|documents| {
+            documents.iter()
+                .filter(|doc| document_names.contains(&doc.name))
+                .filter_map(|doc| doc.get_value(attrs).map(|value| (doc.name.clone(), value)))
+                .collect()
+        })
+    }
+}
+
+///
+/// Settings of a collection read from `CONFIG_FILE` in the collection directory.
+///
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectionConfig {
+    /// a pipeline applied to attributes of lookups
+    pub pipeline: AttrsPipeline,
+    /// known attributes, any attributes are allowed if empty
+    pub schema: Schema,
+}
+
+impl CollectionConfig {
+    ///
+    /// Check that the pipeline only produces attributes known to the schema.
+    /// Attributes which other attributes are derived from stay in lookups, so they must be known as well.
+    ///
+    fn validate(&self) -> Result<(), String> {
+        self.pipeline.validate()?;
+        if self.schema.is_empty() {
+            return Ok(());
+        }
+        let produced = self.pipeline.aliases.values().chain(self.pipeline.derive.iter().map(|it| &it.name));
+        if let Some(name) = produced.into_iter().find(|name| self.schema.get(name).is_none()) {
+            return Err(format!("attribute {} of the pipeline is not declared in the schema", name));
+        }
+        match self.pipeline.derive.iter().find(|it| self.schema.get(&it.from).is_none()) {
+            Some(derived) => Err(format!(
+                "attribute {} which {} is derived from is not declared in the schema", &derived.from, &derived.name
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TryFrom<&path::Path> for CollectionConfig {
+    type Error = CollectionError;
+    ///
+    /// Read `CONFIG_FILE` from a collection directory `path`, a missing file means default settings.
+    ///
+    fn try_from(path: &path::Path) -> Result<Self, Self::Error> {
+        let path = path.join(CONFIG_FILE);
+        if ! path.is_file() {
+            return Ok(Self::default());
+        }
+        let error = |err: String| CollectionError::ConfigError(format!("{}: {}", path.display(), err));
+        let mut content = String::new();
+        std::fs::File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .map_err(|err| error(err.to_string()))?;
+        let config: Self = serde_yaml::from_str(&content).map_err(|err| error(err.to_string()))?;
+        config.validate().map_err(error)?;
+        Ok(config)
+    }
+}
+
+///
+/// A document which could not be loaded.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct BadDocument {
+    pub path: path::PathBuf,
+    /// `io`, `parse`, `content` or `duplicate`
+    pub kind: &'static str,
+    pub message: String,
+    /// a file which declares the same document first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<path::PathBuf>,
+    /// a position of a YAML parse error
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// time spent on the file in milliseconds
+    pub elapsed_ms: f64,
+}
+
+impl From<(&path::Path, &DocumentError, Duration)> for BadDocument {
+    fn from(item: (&path::Path, &DocumentError, Duration)) -> Self {
+        let (path, err, elapsed) = item;
+        let (kind, message, location) = match err {
+            DocumentError::StdIoError(err) => ("io", err.to_string(), None),
+            DocumentError::ParseError(err) => ("parse", err.to_string(), err.location()),
+            DocumentError::ContentError(err) => ("content", err.clone(), None),
+        };
+        Self {
+            path: path.to_path_buf(),
+            kind,
+            message,
+            duplicate_of: None,
+            line: location.as_ref().map(|it| it.line()),
+            column: location.as_ref().map(|it| it.column()),
+            elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+impl BadDocument {
+    ///
+    /// A document `duplicate` which is declared in the file `first` as well
+    /// or has overrides with the same keys if `first` is the file of the document itself.
+    ///
+    fn duplicate(first: &path::Path, duplicate: &Document) -> Self {
+        let message = match first == duplicate.path {
+            true => duplicate.duplicate_overrides.join("; "),
+            false => format!("document {}/{} is declared in {} as well", &duplicate.collection, &duplicate.name, first.display()),
+        };
+        Self {
+            path: duplicate.path.clone(),
+            kind: "duplicate",
+            message,
+            duplicate_of: Some(first.to_path_buf()).filter(|it| it != &duplicate.path),
+            line: None,
+            column: None,
+            elapsed_ms: 0.0,
+        }
+    }
+}
+
+///
+/// A report of loading a collection directory. Documents which could not be loaded
+/// are listed in `bad_documents` if `ignore_bad_documents` is set.
+///
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LoadReport {
+    /// when loading finished, seconds since the Unix epoch
+    pub loaded_at: u64,
+    /// time spent on loading in milliseconds
+    pub elapsed_ms: f64,
+    pub total_files: usize,
+    pub total_documents: usize,
+    pub bad_documents: Vec<BadDocument>,
+    /// duplicates which were ignored, the first declared document or override is kept
+    pub duplicates: Vec<BadDocument>,
+    /// workers which parsed documents
+    pub threads: usize,
+}
+
+impl LoadReport {
+    ///
+    /// Parsed files per second.
+    ///
+    pub fn files_per_sec(&self) -> f64 {
+        match self.elapsed_ms > 0.0 {
+            true => self.total_files as f64 * 1000.0 / self.elapsed_ms,
+            false => 0.0,
         }
     }
 }
 
-impl TryFrom<(&path::PathBuf, bool)> for Collection {
+///
+/// Options applied to documents while loading a collection.
+///
+#[derive(Debug, Clone, Default)]
+pub struct CollectionOptions {
+    /// skip documents which could not be loaded
+    pub ignore_bad_documents: bool,
+    /// keep the first of documents declared twice and the first of overrides with the same keys
+    pub ignore_duplicates: bool,
+    /// merge strategy of documents with `merge_overrides` set and no `merge_strategy` given
+    pub merge_strategy: MergeStrategy,
+    /// attributes which values are paths like `base/web/prod`, i.e. `hostgroup` or `location`
+    pub hierarchical_attrs: Vec<String>,
+    /// workers parsing documents, all available CPUs are used if 0
+    pub load_threads: usize,
+}
+
+impl TryFrom<(&path::PathBuf, &CollectionOptions)> for Collection {
     type Error = CollectionError;
     ///
     /// Load documents from specified directory.
-    /// If `ignore_bad` is true it will return `CollectionError::DocumentError`
+    /// If `options.ignore_bad_documents` is false it will return `CollectionError::DocumentError`
     /// otherwise errors will be ignored.
     /// If none documents loaded then `CollectionError::DocumentsNotFound` will be returned.
+    /// A bad `CONFIG_FILE` always fails with `CollectionError::ConfigError`.
+    /// Documents using attributes not declared in the schema of the config are bad documents.
+    /// Documents declared in many files and documents with overrides with the same keys are bad documents
+    /// unless `options.ignore_duplicates` is set, then the first of them is kept.
+    /// Files are parsed in parallel (see `load_files`) but merged in the order of their paths,
+    /// so the same one is always the first.
     ///
-    fn try_from(item: (&path::PathBuf, bool)) -> Result<Self, Self::Error> {
-        let follow_links = true;
-        let (path, ignore_bad) = item;
-        let mut this = Self { documents: HashMap::new() };
+    fn try_from(item: (&path::PathBuf, &CollectionOptions)) -> Result<Self, Self::Error> {
+        let (path, options) = item;
+        let ignore_bad = options.ignore_bad_documents;
+        let config = CollectionConfig::try_from(path.as_path())?;
+        let start_time = Instant::now();
+        let mut this = Self {
+            documents: HashMap::new(),
+            config,
+            path: path.clone(),
+            options: options.clone(),
+            report: LoadReport::default(),
+            order_levels: BTreeSet::new(),
+        };
+        let (threads, loaded) = load_files(path, &this.config.schema, options.load_threads);
+        this.report.threads = threads;
         let mut total: usize = 0;
-        for entry in WalkDir::new(path)
-            .follow_links(follow_links)
-            .into_iter()
-            .filter_map(|e| e.ok()) {
-            let f_name = entry.file_name().to_string_lossy();
-            if (f_name.ends_with(".yml") || f_name.ends_with(".yaml")) && ! f_name.starts_with(".") {
-                match Document::try_from(entry.path()) {
-                    Ok(doc) => {
-                        total += 1;
-                        let documents = this.documents.entry(doc.collection.clone()).or_insert(Vec::new());
-                        documents.push(doc);
-                    },
-                    Err(err) => {
-                        tracing::error!("Could not load document {:?} {:?}", &entry, &err);
-                        if ! ignore_bad {
-                            return Err(CollectionError::DocumentError(err));
-                        }
+        // files of loaded documents by collection and document names
+        let mut seen: HashMap<(String, String), path::PathBuf> = HashMap::new();
+        for LoadedFile { path: file_path, document, elapsed } in loaded {
+            this.report.total_files += 1;
+            let mut doc = match document {
+                Ok(doc) => doc,
+                Err(err) => {
+                    tracing::error!("Could not load document {:?} {:?}", &file_path, &err);
+                    let bad = BadDocument::from((file_path.as_path(), &err, elapsed));
+                    if ! ignore_bad {
+                        return Err(CollectionError::BadDocument(Box::new(bad)));
                     }
+                    this.report.bad_documents.push(bad);
+                    continue;
+                }
+            };
+            let key = (doc.collection.clone(), doc.name.clone());
+            let duplicate = match seen.get(&key) {
+                Some(first) => Some(BadDocument::duplicate(first, &doc)),
+                None if ! doc.duplicate_overrides.is_empty() => Some(BadDocument::duplicate(&doc.path, &doc)),
+                None => None,
+            };
+            if let Some(duplicate) = duplicate {
+                tracing::warn!("Duplicate in document {:?}: {}", &duplicate.path, &duplicate.message);
+                let is_duplicate_document = duplicate.duplicate_of.is_some();
+                match (options.ignore_duplicates, ignore_bad) {
+                    (true, _) => this.report.duplicates.push(duplicate),
+                    (false, true) => { this.report.bad_documents.push(duplicate); continue; },
+                    (false, false) => return Err(CollectionError::BadDocument(Box::new(duplicate))),
                 }
+                // the first declared document is kept, overrides are deduplicated already
+                if is_duplicate_document {
+                    continue;
+                }
+            }
+            seen.insert(key, doc.path.clone());
+            total += 1;
+            if doc.merge_overrides && doc.merge_strategy.is_none() {
+                doc.merge_strategy = Some(options.merge_strategy);
             }
+            doc.hierarchical_attrs = options.hierarchical_attrs.clone();
+            if doc.enabled {
+                this.order_levels.extend(doc.order_list.iter().cloned());
+            }
+            let documents = this.documents.entry(doc.collection.clone()).or_default();
+            documents.push(doc);
         }
+        this.report.total_documents = total;
+        this.report.elapsed_ms = start_time.elapsed().as_secs_f64() * 1000.0;
+        this.report.loaded_at = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |it| it.as_secs());
         match total {
             0 => Err(CollectionError::DocumentsNotFound),
             _ => Ok(this),
@@ -85,12 +379,100 @@ impl TryFrom<(&path::PathBuf, bool)> for Collection {
     }
 }
 
+///
+/// A document file parsed by `load_files`.
+///
+struct LoadedFile {
+    path: path::PathBuf,
+    document: Result<Document, DocumentError>,
+    /// time spent on the file
+    elapsed: Duration,
+}
+
+fn is_document_file(entry: &walkdir::DirEntry) -> bool {
+    let f_name = entry.file_name().to_string_lossy();
+    entry.file_type().is_file() && (f_name.ends_with(".yml") || f_name.ends_with(".yaml")) && ! f_name.starts_with('.')
+}
+
+///
+/// Walk the directory `path` and parse its document files with a pool of `threads` workers,
+/// all available CPUs are used if `threads` is 0. Files are parsed while the directory
+/// is still walked, results are returned in the order of file paths whatever order
+/// workers finish in. Returns the number of workers and the parsed files.
+///
+fn load_files(path: &path::Path, schema: &Schema, threads: usize) -> (usize, Vec<LoadedFile>) {
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, |it| it.get()),
+        threads => threads,
+    };
+    let (path_tx, path_rx) = mpsc::channel::<(usize, path::PathBuf)>();
+    let (file_tx, file_rx) = mpsc::channel::<(usize, LoadedFile)>();
+    let path_rx = Mutex::new(path_rx);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let (path_rx, file_tx) = (&path_rx, file_tx.clone());
+            scope.spawn(move || loop {
+                // the lock is released before the file is parsed
+                let next = path_rx.lock().map_or(None, |rx| rx.recv().ok());
+                let Some((index, path)) = next else { break };
+                let file_time = Instant::now();
+                let document = Document::try_from(path.as_path())
+                    .and_then(|doc| schema.validate_document(&doc).map(|_| doc).map_err(DocumentError::from));
+                let loaded = LoadedFile { path, document, elapsed: file_time.elapsed() };
+                if file_tx.send((index, loaded)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(file_tx);
+        let entries = WalkDir::new(path)
+            .follow_links(true)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(is_document_file);
+        for (index, entry) in entries.enumerate() {
+            if path_tx.send((index, entry.into_path())).is_err() {
+                break;
+            }
+        }
+        drop(path_tx);
+    });
+    let mut files = file_rx.into_iter().collect::<Vec<(usize, LoadedFile)>>();
+    files.sort_by_key(|(index, _)| *index);
+    (threads, files.into_iter().map(|(_, file)| file).collect())
+}
+
 #[derive(Debug)]
 pub enum CollectionError {
     DocumentError(DocumentError),
+    BadDocument(Box<BadDocument>),
     DocumentNotFound(String, String),   // collection name, document name
     DocumentsNotFound,
     CollectionNotFound(String),
+    ConfigError(String),
+    AttrsError(String),
+    LoadError(String),
+    HiddenValues(Vec<String>),          // `collection/document` names of hidden values which would be masked
+}
+
+impl fmt::Display for CollectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollectionError::DocumentError(err) => write!(f, "bad document: {}", err),
+            CollectionError::BadDocument(bad) => match (bad.line, bad.column) {
+                (Some(line), Some(column)) => write!(f, "bad document {}:{}:{}: {}", bad.path.display(), line, column, &bad.message),
+                _ => write!(f, "bad document {}: {}", bad.path.display(), &bad.message),
+            },
+            CollectionError::DocumentNotFound(collection, document) => write!(f, "document {}/{} not found", collection, document),
+            CollectionError::DocumentsNotFound => write!(f, "no documents found"),
+            CollectionError::CollectionNotFound(collection) => write!(f, "collection {} not found", collection),
+            CollectionError::ConfigError(err) => write!(f, "bad collection config {}", err),
+            CollectionError::AttrsError(err) => write!(f, "bad attributes: {}", err),
+            CollectionError::LoadError(err) => write!(f, "could not load collection: {}", err),
+            CollectionError::HiddenValues(names) => write!(f, "hidden values of {} are not revealed", names.join(", ")),
+        }
+    }
 }
 
 impl From<DocumentError> for CollectionError {
@@ -98,3 +480,151 @@ impl From<DocumentError> for CollectionError {
         CollectionError::DocumentError(inner)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeSet, HashMap};
+    use super::{Attrs, Collection, CollectionConfig, CollectionError, CollectionOptions, Document};
+    use crate::collection::testing::{doc_yaml, DOC_YAML};
+
+
+    #[test]
+    fn test_get_values_override_disabled() {
+        let doc = Document::try_from(DOC_YAML).expect("could not parse document");
+        let collection = Collection { documents: HashMap::from([("world".into(), vec![doc])]), ..Default::default() };
+        let attrs = Attrs::from(HashMap::<String, String>::from([("fqdn".into(), "host.example.com".into())]));
+        let values = collection.get_values(&"world".into(), &attrs).expect("collection not found");
+        assert_eq!(values.get("hello"), Some(&serde_json::json!("default")));
+    }
+
+    #[test]
+    fn test_get_document_values() {
+        let doc = Document::try_from(DOC_YAML).expect("could not parse document");
+        let collection = Collection { documents: HashMap::from([("world".into(), vec![doc])]), ..Default::default() };
+        let values = collection.get_document_values(&"world".into(), &["hello".into()], &Attrs::default())
+            .expect("collection not found");
+        assert_eq!(values.get("hello"), Some(&serde_json::json!("default")));
+        let values = collection.get_document_values(&"world".into(), &["unknown".into()], &Attrs::default())
+            .expect("collection not found");
+        assert!(values.is_empty());
+        assert!(collection.get_document_values(&"unknown".into(), &[], &Attrs::default()).is_none());
+    }
+
+    #[test]
+    fn test_get_values_omitted() {
+        let doc = Document::try_from(DOC_YAML.replace("omit: false\nmerge_default", "omit: true\nmerge_default").as_str())
+            .expect("could not parse document");
+        let collection = Collection { documents: HashMap::from([("world".into(), vec![doc])]), ..Default::default() };
+        let values = collection.get_values(&"world".into(), &Attrs::default()).expect("collection not found");
+        assert!(values.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_attrs() {
+        let doc = Document::try_from(DOC_YAML.replace("override: false", "override: true").as_str())
+            .expect("could not parse document");
+        let config: CollectionConfig = serde_yaml::from_str("pipeline: {aliases: {host: fqdn}}").expect("could not parse config");
+        let collection = Collection { documents: HashMap::from([("world".into(), vec![doc])]), config, ..Default::default() };
+        let attrs = Attrs::from(HashMap::<String, String>::from([("host".into(), "host.example.com".into())]));
+        let attrs = collection.resolve_attrs(&attrs).expect("attributes are not valid");
+        let values = collection.get_values(&"world".into(), &attrs).expect("collection not found");
+        assert_eq!(values.get("hello"), Some(&serde_json::json!("overridden")));
+        assert!(serde_yaml::from_str::<CollectionConfig>("unknown: true").is_err());
+
+        let config = |schema: &str| serde_yaml::from_str::<CollectionConfig>(&format!(
+            "pipeline: {{derive: [{{name: subnet, from: ip, rule: subnet}}]}}\nschema: {}", schema
+        )).expect("could not parse config");
+        assert!(config("[{name: ip}, {name: subnet}]").validate().is_ok());
+        assert!(config("[{name: subnet}]").validate().is_err_and(|err| err.contains("attribute ip ")));
+        assert!(config("[{name: ip}]").validate().is_err_and(|err| err.contains("attribute subnet ")));
+    }
+
+    #[test]
+    fn test_resolve_attrs_limits() {
+        let collection = Collection {
+            options: CollectionOptions { hierarchical_attrs: vec!["hostgroup".into()], ..Default::default() },
+            order_levels: BTreeSet::from([vec!["env".to_string(), "hostgroup".to_string()]]),
+            ..Default::default()
+        };
+        let attrs = |env: usize, hostgroup: usize| {
+            let mut attrs = Attrs::default();
+            attrs.insert_values("env", (0..env).map(|i| format!("env{}", i)).collect());
+            attrs.insert_values("hostgroup", (0..hostgroup).map(|i| format!("base/group{}", i)).collect());
+            attrs
+        };
+        assert!(collection.resolve_attrs(&attrs(4, 8)).is_ok());
+        // too many values of one attribute
+        assert!(matches!(collection.resolve_attrs(&attrs(33, 1)), Err(CollectionError::AttrsError(_))));
+        // 32 values times 2 levels of 32 hostgroups
+        assert!(matches!(collection.resolve_attrs(&attrs(32, 32)), Err(CollectionError::AttrsError(_))));
+    }
+
+    #[test]
+    fn test_load_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_path_buf();
+        std::fs::write(path.join("hello.yaml"), DOC_YAML).unwrap();
+        std::fs::write(path.join("bad.yaml"), "description: bad\nparameter: [\n").unwrap();
+
+        let options = CollectionOptions { ignore_bad_documents: true, ..Default::default() };
+        let collection = Collection::try_from((&path, &options)).expect("could not load collection");
+        assert_eq!((collection.report.total_files, collection.report.total_documents), (2, 1));
+        let bad = &collection.report.bad_documents[0];
+        assert_eq!((bad.path.clone(), bad.kind, bad.line), (path.join("bad.yaml"), "parse", Some(2)));
+
+        let err = Collection::try_from((&path, &CollectionOptions::default())).expect_err("bad document is not reported");
+        assert!(matches!(err, CollectionError::BadDocument(bad) if bad.path == path.join("bad.yaml")));
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_path_buf();
+        std::fs::write(path.join("a.yaml"), DOC_YAML).unwrap();
+        std::fs::write(path.join("b.yaml"), DOC_YAML.replace("default_value: default", "default_value: other")).unwrap();
+
+        let err = Collection::try_from((&path, &CollectionOptions::default())).expect_err("duplicate is not reported");
+        assert!(matches!(err, CollectionError::BadDocument(bad) if bad.kind == "duplicate" && bad.path == path.join("b.yaml")));
+
+        let options = CollectionOptions { ignore_duplicates: true, ..Default::default() };
+        let collection = Collection::try_from((&path, &options)).expect("could not load collection");
+        assert_eq!(collection.report.total_documents, 1);
+        assert_eq!(collection.report.duplicates[0].duplicate_of, Some(path.join("a.yaml")));
+        let values = collection.get_values(&"world".into(), &Attrs::default()).expect("collection not found");
+        assert_eq!(values.get("hello"), Some(&serde_json::json!("default")));
+
+        // overrides with the same keys in one document
+        std::fs::remove_file(path.join("b.yaml")).unwrap();
+        let doc_yaml = doc_yaml(&[("fqdn=host.example.com", "overridden"), ("FQDN=host.example.com", "again")], &["fqdn"]);
+        std::fs::write(path.join("a.yaml"), doc_yaml).unwrap();
+        assert!(Collection::try_from((&path, &CollectionOptions::default())).is_err());
+        let collection = Collection::try_from((&path, &options)).expect("could not load collection");
+        assert_eq!((collection.report.total_documents, collection.report.duplicates.len()), (1, 1));
+        assert_eq!(collection.report.duplicates[0].duplicate_of, None);
+    }
+
+    #[test]
+    fn test_parallel_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_path_buf();
+        for i in 0..64 {
+            let dir = path.join(format!("dir{}", i % 4));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(format!("doc{:02}.yaml", i)), DOC_YAML.replace("parameter: hello", &format!("parameter: p{}", i % 48))).unwrap();
+        }
+        std::fs::write(path.join("dir0/bad.yaml"), "parameter: [").unwrap();
+
+        let load = |load_threads: usize| {
+            let options = CollectionOptions { ignore_bad_documents: true, ignore_duplicates: true, load_threads, ..Default::default() };
+            Collection::try_from((&path, &options)).expect("could not load collection")
+        };
+        let (serial, parallel) = (load(1), load(8));
+        assert_eq!((parallel.report.threads, parallel.report.total_files, parallel.report.total_documents), (8, 65, 48));
+        assert_eq!(parallel.checksums(), serial.checksums());
+        let paths = |c: &Collection| c.report.duplicates.iter()
+            .map(|it| (it.path.clone(), it.duplicate_of.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(paths(&parallel), paths(&serial));
+        assert_eq!(parallel.report.bad_documents[0].path, path.join("dir0/bad.yaml"));
+    }
+}
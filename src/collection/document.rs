@@ -1,11 +1,13 @@
 use serde::{Deserialize, Serialize};
+use super::{matcher::Matcher, attrs::Attrs, models::HIDDEN_VALUE_MASK};
 use std::{
     fmt,
     path,
     io::Error as StdIoError,
     io::Read,
     convert::TryFrom,
-    collections::{HashSet, HashMap},
+    collections::{HashSet, HashMap, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
     time::{Duration, Instant},
 };
 
@@ -27,25 +29,125 @@ pub enum DocumentValueType {
     String,
 }
 
-// OVERRIDE //
+impl DocumentValueType {
+    ///
+    /// Convert a value into the type. `null` is kept as is, an empty string
+    /// of a non string type is converted into `null`.
+    ///
+    /// Strings are parsed into values of other types, i.e. `"true"` becomes `true`
+    /// for `boolean` and `"{a: 1}"` becomes `{"a": 1}` for `hash`.
+    ///
+    pub fn cast(&self, value: &ParamValue) -> Result<ParamValue, String> {
+        let error = || format!("could not convert {} into {:?}", value, self);
+        let parse_yaml = |s: &str| serde_yaml::from_str::<ParamValue>(s).map_err(|_| error());
+        match (self, value) {
+            (_, ParamValue::Null) => Ok(ParamValue::Null),
+            (DocumentValueType::String, ParamValue::String(_)) => Ok(value.clone()),
+            (DocumentValueType::String, ParamValue::Bool(_) | ParamValue::Number(_)) => Ok(ParamValue::String(value.to_string())),
+            (_, ParamValue::String(s)) if s.trim().is_empty() => Ok(ParamValue::Null),
+            (DocumentValueType::Boolean, ParamValue::Bool(_)) => Ok(value.clone()),
+            (DocumentValueType::Boolean, ParamValue::String(s)) => match s.trim().to_lowercase().as_str() {
+                "true" | "yes" | "y" | "on" | "t" | "1" => Ok(ParamValue::Bool(true)),
+                "false" | "no" | "n" | "off" | "f" | "0" => Ok(ParamValue::Bool(false)),
+                _ => Err(error()),
+            },
+            (DocumentValueType::Boolean, ParamValue::Number(n)) => match n.as_i64() {
+                Some(1) => Ok(ParamValue::Bool(true)),
+                Some(0) => Ok(ParamValue::Bool(false)),
+                _ => Err(error()),
+            },
+            (DocumentValueType::Number, ParamValue::Number(_)) => Ok(value.clone()),
+            (DocumentValueType::Number, ParamValue::String(s)) => s.trim().parse::<i64>().map(ParamValue::from)
+                .or_else(|_| s.trim().parse::<f64>().map(ParamValue::from))
+                .map_err(|_| error()),
+            (DocumentValueType::Array, ParamValue::Array(_)) => Ok(value.clone()),
+            (DocumentValueType::Hash, ParamValue::Object(_)) => Ok(value.clone()),
+            (DocumentValueType::Array | DocumentValueType::Hash, ParamValue::String(s)) => {
+                let parsed = parse_yaml(s)?;
+                match (self, &parsed) {
+                    (DocumentValueType::Array, ParamValue::Array(_)) | (DocumentValueType::Hash, ParamValue::Object(_)) => Ok(parsed),
+                    _ => Err(error()),
+                }
+            },
+            (DocumentValueType::Json, ParamValue::String(s)) => serde_json::from_str(s).map_err(|_| error()),
+            (DocumentValueType::Yaml, ParamValue::String(s)) => parse_yaml(s),
+            (DocumentValueType::Json | DocumentValueType::Yaml, _) => Ok(value.clone()),
+            _ => Err(error()),
+        }
+    }
+}
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Serialize)]
-pub struct Override {
-    pub omit: bool,
-    pub value: ParamValue,
-    #[serde(rename="match", deserialize_with = "str2attr")]
-    pub attrs: HashMap<String, String>,
+// MERGE STRATEGY //
+
+///
+/// How matched overrides are combined.
+/// More specific overrides (earlier in `order_list`) always win.
+///
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// the first matched override wins
+    First,
+    /// top level keys of matched overrides are merged
+    Hash,
+    /// matched overrides are merged recursively
+    #[default]
+    Deep,
 }
 
+// OVERRIDE //
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct OverrideV2 {
     pub omit: bool,
     pub value: ParamValue,
+    /// a compiled matcher of an override with patterns in its key
+    #[serde(skip)]
+    pub matcher: Option<Matcher>,
+    /// a position of the override in the document
+    #[serde(skip)]
+    pub position: usize,
 }
 
 pub type DocumentOverrides = HashMap<String, OverrideV2>;
 
+// LOOKUP TRACE //
+
+///
+/// A single `order_list` level visited by a lookup.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct LookupStep {
+    /// attributes of the level
+    pub order: Vec<String>,
+    /// the key built by `build_compare_key` for the level
+    pub compare_key: String,
+    /// an override with `compare_key` exists
+    pub matched: bool,
+    /// the matched override is omitted
+    pub omitted: bool,
+    /// the matched override value was merged
+    pub merged: bool,
+    /// the matched override value
+    pub value: Option<ParamValue>,
+    /// a key of the matched override if it was matched by patterns
+    pub pattern: Option<String>,
+}
+
+///
+/// Steps a lookup went through to resolve a value, see `Document::explain`.
+///
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LookupTrace {
+    pub overrides_enabled: bool,
+    pub merge_strategy: MergeStrategy,
+    pub initial_value: ParamValue,
+    pub steps: Vec<LookupStep>,
+    pub default_used: bool,
+    pub omitted: bool,
+    pub value: ParamValue,
+}
+
 /*****************************
     DOCUMENT VERSION 2
 *****************************/
@@ -61,12 +163,22 @@ pub struct Document {
     pub name: String,
     #[serde(rename = "puppetclass_name")]
     pub collection: String,
-    omit: bool,
+    pub omit: bool,
     pub merge_default: bool,
     pub merge_overrides: bool,
+    #[serde(default)]
+    pub merge_strategy: Option<MergeStrategy>,
+    #[serde(default)]
+    pub avoid_duplicates: bool,
     //pub overrides: Option<Vec<OverrideV2>>,
-    #[serde(rename = "override_values", deserialize_with = "deser_overrides")]
+    /// overrides as they are written, they are turned into `overrides` by `build_overrides`
+    #[serde(rename = "override_values")]
+    override_items: Vec<OverrideItem>,
+    #[serde(skip)]
     pub overrides: DocumentOverrides,
+    /// keys of overrides declared more than once, only the first of them is kept
+    #[serde(skip)]
+    pub duplicate_overrides: Vec<String>,
     #[serde(rename = "override_value_order", deserialize_with = "str2list_of_attrs")]
     pub order_list: Vec<Vec<String>>,
 
@@ -74,6 +186,19 @@ pub struct Document {
     pub validator_rule: Option<String>,
     pub validator_type: Option<String>,
 
+    /// attributes which values are paths like `base/web/prod`,
+    /// an override of an ancestor applies to all its descendants
+    #[serde(skip)]
+    pub hierarchical_attrs: Vec<String>,
+    /// keys of overrides with patterns in the order of the document
+    #[serde(skip)]
+    pub patterns: Vec<String>,
+    /// a hash of the source of the document to find out whether it changed on reload
+    #[serde(skip)]
+    pub checksum: u64,
+    /// a file the document was loaded from
+    #[serde(skip)]
+    pub path: path::PathBuf,
     /*** Extra attributes for ease management ***/
     // #[serde(skip)]
     // pub attr_list: Vec<String>, // a list of attributes required to lookup value
@@ -89,10 +214,14 @@ impl Document {
         let mut items: HashSet<String> = HashSet::default();
         for row_items in self.order_list.iter() {
             row_items.iter().for_each(|it| {
-                items.insert(it.to_lowercase().into());
+                items.insert(it.to_lowercase());
             })
         }
-        items.iter().map(|it| it.clone()).collect::<Vec<String>>()
+        items.iter().cloned().collect::<Vec<String>>()
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden_value.unwrap_or(false)
     }
 
     pub fn override_order(&self) -> Vec<String> {
@@ -100,33 +229,241 @@ impl Document {
     }
 
     ///
-    /// Loog up a value from the document for given attributes
+    /// Loog up a value from the document for given attributes.
+    /// `None` is returned if the value is omitted, i.e. the parameter must not be managed.
+    ///
+    pub fn get_value(&self, attrs: &Attrs) -> Option<ParamValue> {
+        self.lookup(attrs, None)
+    }
+
+    ///
+    /// Look up a value like `get_value` does and record every step of the lookup.
+    ///
+    pub fn explain(&self, attrs: &Attrs) -> LookupTrace {
+        let mut trace = LookupTrace::default();
+        let value = self.lookup(attrs, Some(&mut trace));
+        trace.omitted = value.is_none();
+        trace.value = value.unwrap_or_default();
+        trace
+    }
+
+    ///
+    /// Get the default value or `None` if the default value is omitted.
+    ///
+    fn default_or_omit(&self) -> Option<ParamValue> {
+        match self.omit {
+            true => None,
+            false => Some(self.default_value.clone()),
+        }
+    }
+
+    ///
+    /// Get a merge strategy of the document.
+    /// `merge_strategy` wins over `merge_overrides` if both are set.
     ///
-    pub fn get_value(&self, attrs: &HashMap<String, String>) -> ParamValue {
+    pub fn merge_strategy(&self) -> MergeStrategy {
+        match (self.merge_strategy, self.merge_overrides) {
+            (Some(strategy), _) => strategy,
+            (None, true) => MergeStrategy::default(),
+            (None, false) => MergeStrategy::First,
+        }
+    }
+
+    fn lookup(&self, attrs: &Attrs, mut trace: Option<&mut LookupTrace>) -> Option<ParamValue> {
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.overrides_enabled = self.enabled;
+        }
+        // like in Foreman, a parameter with disabled overrides always has its default value
+        if ! self.enabled {
+            if let Some(trace) = trace {
+                trace.default_used = true;
+            }
+            return self.default_or_omit();
+        }
         let is_hash = [
             DocumentValueType::Hash,
             DocumentValueType::Json,
             DocumentValueType::Yaml
         ].contains(&self.value_type);
-        let mut value: ParamValue = match self.merge_default && is_hash {
-            true => self.default_value.clone(),
-            false => serde_json::json!({}),
+        let is_array = self.value_type == DocumentValueType::Array;
+        let mut value: ParamValue = match (self.merge_default && is_hash, is_array) {
+            (true, _) => self.default_value.clone(),
+            (false, true) => serde_json::json!([]),
+            (false, false) => serde_json::json!({}),
+        };
+        let strategy = match is_hash || is_array {
+            true => self.merge_strategy(),
+            false => MergeStrategy::First,
         };
-        let need_merge: bool = self.merge_overrides && is_hash;
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.merge_strategy = strategy;
+            trace.initial_value = value.clone();
+        }
+        // matched values, the most specific first
+        let mut matched: Vec<&ParamValue> = Vec::new();
         for order_key in &self.order_list {
-            let override_key = build_compare_key(&attrs, &order_key, true);
-            match self.overrides.get(&override_key) {
-                Some(ref matcher) => match need_merge {
-                    true => if ! matcher.omit { json_patch::merge(&mut value, &matcher.value) },
-                    false =>  { value = matcher.value.clone(); break; }
+            // the most specific key goes first
+            let mut override_keys = build_compare_keys(attrs, order_key, &self.hierarchical_attrs);
+            let position = override_keys.iter().position(|key| self.overrides.contains_key(key)).unwrap_or(0);
+            let override_key = override_keys.swap_remove(position);
+            // overrides with exact keys win over overrides with patterns
+            let pattern = match self.overrides.contains_key(&override_key) {
+                true => None,
+                false => self.find_pattern(attrs, order_key),
+            };
+            let matcher = self.overrides.get(pattern.unwrap_or(&override_key));
+            let mut step = LookupStep {
+                order: order_key.clone(),
+                compare_key: override_key,
+                matched: matcher.is_some(),
+                omitted: matcher.is_some_and(|it| it.omit),
+                merged: false,
+                value: matcher.map(|it| it.value.clone()),
+                pattern: pattern.cloned(),
+            };
+            let found = match matcher {
+                Some(matcher) => match strategy {
+                    // an omitted override means the parameter is not managed
+                    MergeStrategy::First => Some((! matcher.omit).then(|| matcher.value.clone())),
+                    MergeStrategy::Hash | MergeStrategy::Deep => {
+                        if ! matcher.omit {
+                            matched.push(&matcher.value);
+                            step.merged = true;
+                        }
+                        None
+                    },
                 }
-                None => ()
+                None => None
+            };
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.steps.push(step);
+            }
+            if let Some(found) = found {
+                return found;
+            }
+        }
+        if is_array {
+            return self.merge_arrays(matched, trace);
+        }
+        // an empty value is a legitimate value as long as something matched
+        let use_default = matched.is_empty();
+        // less specific values are merged first, so more specific ones overwrite them
+        for item in matched.into_iter().rev() {
+            match strategy {
+                MergeStrategy::Hash => merge_hash(&mut value, item),
+                _ => json_patch::merge(&mut value, item),
+            }
+        }
+        if let Some(trace) = trace {
+            trace.default_used = use_default;
+        }
+        match use_default {
+            true => self.default_or_omit(),
+            false => Some(value),
+        }
+    }
+
+    ///
+    /// Find the first override with patterns for the `order_key` level matching `attrs`.
+    ///
+    fn find_pattern(&self, attrs: &Attrs, order_key: &Vec<String>) -> Option<&String> {
+        self.patterns.iter().find(|key| {
+            self.overrides.get(*key)
+                .and_then(|it| it.matcher.as_ref())
+                .is_some_and(|matcher| matcher.attrs() == order_key && matcher.is_match(attrs, &self.hierarchical_attrs))
+        })
+    }
+
+    ///
+    /// Concatenate matched arrays, the most specific first, and the default value
+    /// if `merge_default` is set. Duplicates are removed if `avoid_duplicates` is set.
+    ///
+    fn merge_arrays(&self, matched: Vec<&ParamValue>, trace: Option<&mut LookupTrace>) -> Option<ParamValue> {
+        let use_default = matched.is_empty();
+        if let Some(trace) = trace {
+            trace.default_used = use_default || self.merge_default;
+        }
+        if use_default {
+            return self.default_or_omit();
+        }
+        let mut items: Vec<ParamValue> = Vec::new();
+        let mut extend = |value: &ParamValue| match value {
+            ParamValue::Array(values) => items.extend(values.iter().cloned()),
+            value => items.push(value.clone()),
+        };
+        matched.into_iter().for_each(&mut extend);
+        if self.merge_default {
+            extend(&self.default_value);
+        }
+        if self.avoid_duplicates {
+            let mut unique: Vec<ParamValue> = Vec::with_capacity(items.len());
+            items.into_iter().for_each(|item| if ! unique.contains(&item) { unique.push(item) });
+            items = unique;
+        }
+        Some(ParamValue::Array(items))
+    }
+
+    ///
+    /// Convert the default value and override values into `value_type`.
+    /// Values of omitted overrides are never returned, so they are converted only if possible.
+    ///
+    fn cast_values(&mut self) -> Result<(), DocumentError> {
+        let value_type = self.value_type.clone();
+        let hidden = self.is_hidden();
+        let error = |value: &ParamValue| format!("could not convert {} into {:?}", show_value(value, hidden), &value_type);
+        self.default_value = value_type.cast(&self.default_value)
+            .map_err(|_| format!("{}/{}: default value: {}", &self.collection, &self.name, error(&self.default_value)))?;
+        for (key, item) in self.overrides.iter_mut() {
+            match value_type.cast(&item.value) {
+                Ok(value) => item.value = value,
+                Err(_) if item.omit => (),
+                Err(_) => return Err(format!("{}/{}: override {}: {}", &self.collection, &self.name, key, error(&item.value)).into()),
             }
         }
-        match value == serde_json::json!({}) {
-            true => self.default_value.clone(),
-            false => value,
+        Ok(())
+    }
+
+    ///
+    /// Check the default value and override values against `validator_type` and `validator_rule`.
+    /// Supported validators are
+    /// - `regexp` a value must match the regular expression `validator_rule`
+    /// - `list` a value must be one of comma separated values of `validator_rule`
+    ///
+    /// `null` values and values of omitted overrides are not checked.
+    ///
+    fn validate(&self) -> Result<(), DocumentError> {
+        let rule = self.validator_rule.clone().unwrap_or_default();
+        let check: Box<dyn Fn(&str) -> bool> = match self.validator_type.as_deref().map(str::trim) {
+            None | Some("") => return Ok(()),
+            Some("regexp") => {
+                let re = regex::Regex::new(&rule)
+                    .map_err(|err| format!("{}/{}: bad validator rule {:?}: {}", &self.collection, &self.name, &rule, err))?;
+                Box::new(move |value| re.is_match(value))
+            },
+            Some("list") => {
+                let items = rule.split_terminator(',').map(|it| it.trim().to_string()).collect::<Vec<String>>();
+                Box::new(move |value| items.iter().any(|it| it == value.trim()))
+            },
+            Some(other) => return Err(format!("{}/{}: unknown validator type {}", &self.collection, &self.name, other).into()),
+        };
+        let is_valid = |value: &ParamValue| match value {
+            ParamValue::Null => true,
+            ParamValue::String(s) => check(s),
+            value => check(&value.to_string()),
+        };
+        if ! is_valid(&self.default_value) {
+            return Err(format!(
+                "{}/{}: default value {} does not match {:?}", &self.collection, &self.name, show_value(&self.default_value, self.is_hidden()), &rule
+            ).into());
+        }
+        for (key, item) in self.overrides.iter().filter(|(_, it)| ! it.omit) {
+            if ! is_valid(&item.value) {
+                return Err(format!(
+                    "{}/{}: override {} value {} does not match {:?}", &self.collection, &self.name, key, show_value(&item.value, self.is_hidden()), &rule
+                ).into());
+            }
         }
+        Ok(())
     }
 
     pub fn get_overrides(&self) -> DocumentOverrides {
@@ -140,7 +477,9 @@ impl TryFrom<&path::Path> for Document {
     fn try_from(path: &path::Path) -> Result<Self, Self::Error> {
         let mut content = String::new();
         std::fs::File::open(path)?.read_to_string(&mut content)?;
-        Ok(Document::try_from(content.as_str())?)
+        let mut doc = Document::try_from(content.as_str())?;
+        doc.path = path.to_path_buf();
+        Ok(doc)
     }
 }
 
@@ -157,13 +496,51 @@ impl TryFrom<&str> for Document {
         let mut item: Document = serde_yaml::from_str(buffer)?;
         item.name = item.name.to_lowercase();
         item.collection = item.collection.to_lowercase();
+        (item.overrides, item.duplicate_overrides) = build_overrides(std::mem::take(&mut item.override_items))?;
+        let mut patterns = item.overrides.iter()
+            .filter(|(_, it)| it.matcher.is_some())
+            .map(|(key, it)| (it.position, key.clone()))
+            .collect::<Vec<(usize, String)>>();
+        patterns.sort();
+        item.patterns = patterns.into_iter().map(|(_, key)| key).collect();
+        let mut hasher = DefaultHasher::new();
+        buffer.hash(&mut hasher);
+        item.checksum = hasher.finish();
+        item.cast_values()?;
+        item.validate()?;
         tracing::info!("loaded document {}/{} in {:?}", &item.collection, &item.name, &start.elapsed());
         Ok(item)
     }
 }
 
 ///
-/// Deserialize a list of matchers into a hashmap of matchers
+/// Merge top level keys of `patch` into `value`. Nested values are replaced, not merged.
+/// If any of values is not an object then `value` is replaced with `patch`.
+///
+fn merge_hash(value: &mut ParamValue, patch: &ParamValue) {
+    match (value.as_object_mut(), patch.as_object()) {
+        (Some(target), Some(patch)) => {
+            patch.iter().for_each(|(key, item)| { target.insert(key.clone(), item.clone()); });
+        },
+        _ => *value = patch.clone(),
+    }
+}
+
+///
+/// An override as it is written in a document.
+///
+#[derive(Clone, Deserialize)]
+struct OverrideItem {
+    omit: bool,
+    value: ParamValue,
+    #[serde(rename = "match")]
+    key: String,
+}
+
+///
+/// Turn a list of matchers into a hashmap of matchers.
+/// Keys with patterns (see `matcher`) get compiled matchers, other keys are normalized
+/// by `normalize_override_key`.
 ///
 /// A list of such elements
 /// ```not_run
@@ -171,57 +548,33 @@ impl TryFrom<&str> for Document {
 ///   omit: false
 ///   value: "Hello, World"
 /// ```
-/// is deserialized into hashmap of these elements
+/// is turned into hashmap of these elements
 /// ```not_run
 /// key1=value1,key2=value:
 ///     omit: false
 ///     value: "Hello, World"
 /// ```
 ///
-fn deser_overrides<'de, D>(deserializer: D) -> std::result::Result<DocumentOverrides, D::Error>
-where
-    D: serde::de::Deserializer<'de>,
-{
-    #[derive(Deserialize)]
-    struct Matcher {
-        pub omit: bool,
-        pub value: ParamValue,
-        #[serde(rename="match", deserialize_with = "match_to_string")]
-        pub key: String,
-    }
-    let items: Vec<Matcher> = Deserialize::deserialize(deserializer)?;
-    let items = items.into_iter()
-        .map(|it| (it.key.clone(), OverrideV2 { omit: it.omit, value: it.value.clone() }))
-        .collect::<DocumentOverrides>();
-    Ok(items)
-}
-
-
-
-/*
-Split up a matcher key given as string into key value pairs.
-Ex., domain=example.com,is_virtual=true => {domain: example.com, is_virtual: true}
-*/
-fn str2attr<'de, D>(deserializer: D) -> std::result::Result<HashMap<String, String>, D::Error>
-where
-    D: serde::de::Deserializer<'de>,
-{
-    let item: &str = Deserialize::deserialize(deserializer)?;
-    let mut result: HashMap<String, String> = HashMap::new();
-    for pair in item.split_terminator(",").collect::<Vec<&str>>() {
-        let (attr, value) = pair.split_once('=')
-            .ok_or_else(||serde::de::Error::custom(String::from(format!("matcher {}, attr {}", &item, &pair))))?;
-        result.insert(attr.to_lowercase().into(), value.into());
+/// Keys which are the same once normalized are duplicates, the first override is kept
+/// and descriptions of the others are returned.
+///
+fn build_overrides(items: Vec<OverrideItem>) -> Result<(DocumentOverrides, Vec<String>), String> {
+    let mut overrides = DocumentOverrides::new();
+    let mut duplicates = Vec::new();
+    for (position, it) in items.into_iter().enumerate() {
+        let matcher = Matcher::try_from(it.key.as_str())?;
+        let (key, matcher) = match matcher.is_pattern() {
+            true => (matcher.key(), Some(matcher)),
+            false => (normalize_override_key(&it.key), None),
+        };
+        match overrides.get(&key) {
+            Some(first) => duplicates.push(format!(
+                "override {:?} (#{}) duplicates {} (#{})", &it.key, position + 1, &key, first.position + 1
+            )),
+            None => { overrides.insert(key, OverrideV2 { omit: it.omit, value: it.value, matcher, position }); },
+        }
     }
-    Ok(result)
-}
-
-fn match_to_string<'de, D>(deserializer: D) -> std::result::Result<String, D::Error>
-where
-    D: serde::de::Deserializer<'de>,
-{
-    let item: &str = Deserialize::deserialize(deserializer)?;
-    Ok(normalize_override_key(item).into())
+    Ok((overrides, duplicates))
 }
 
 ///
@@ -230,13 +583,14 @@ where
 ///  - `[fqdn]`
 ///  - `[domain]`
 ///  - `[hostgroup,is_virtual,domain]`
+///
 /// into a hashset
 ///  - `fqdn`
 ///  - `domain`
 ///  - `hostgroup`
 ///  - `is_virtual`
 ///
-fn extract_attrs(list_attrs: &Vec<Vec<String>>) -> Vec<String> {
+fn extract_attrs(list_attrs: &[Vec<String>]) -> Vec<String> {
     let mut req_attrs: Vec<String> = Vec::new();
     list_attrs.iter().for_each(|list| {
         list.iter().for_each(|it| {
@@ -249,12 +603,23 @@ fn extract_attrs(list_attrs: &Vec<Vec<String>>) -> Vec<String> {
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum DocumentError {
     StdIoError(std::io::Error),
     ParseError(serde_yaml::Error),
     ContentError(String),
 }
 
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentError::StdIoError(err) => write!(f, "{}", err),
+            DocumentError::ParseError(err) => write!(f, "{}", err),
+            DocumentError::ContentError(err) => write!(f, "{}", err),
+        }
+    }
+}
+
 impl From<String> for DocumentError {
     fn from(inner: String) -> Self {
         DocumentError::ContentError(inner)
@@ -291,7 +656,7 @@ impl From<&Document> for DocumentInfo {
             collection_name: doc.collection.clone(),
             overrides_enabled: doc.enabled,
             total_overrides: doc.overrides.len(),
-            override_attrs: doc.override_attrs().iter().map(|it| it.clone()).collect(),
+            override_attrs: doc.override_attrs().to_vec(),
             order_list: doc.order_list.iter().map(|it| it.join(",")).collect(),
             value_type: doc.value_type.clone(),
             default_value: doc.default_value.clone(),
@@ -316,7 +681,7 @@ pub fn normalize_override_key(value: &str) -> String {
             ).to_lowercase()
     };
     let mut value: Vec<String> = value.split_terminator(',')
-        .map(|keyvalue| ensure(&keyvalue))
+        .map(ensure)
         .collect();
     value.sort();
     value.join(",")
@@ -357,15 +722,103 @@ fn normalize_attrs(attrs: &HashMap<String, String>, to_lowercase: bool) -> Strin
 /// The function calls `normalize_attrs` and work with only attributes provided in `list_attrs`.
 /// Attributes not found in `attrs` get empty values
 ///
-fn build_compare_key(attrs: &HashMap<String, String>, list_attrs: &Vec<String>, to_lowercase: bool) -> String {
+fn build_compare_key(attrs: &HashMap<String, String>, list_attrs: &[String], to_lowercase: bool) -> String {
     // the function is not optimal - too many clones
-    assert_eq!(list_attrs.len() > 0, true);
-    let attrs = list_attrs.into_iter()
+    assert!(!list_attrs.is_empty());
+    let attrs = list_attrs.iter()
         .map(|it| (it.to_lowercase().clone(), attrs.get(it).unwrap_or(&"".into()).clone()))
         .collect::<HashMap<String, String>>();
     normalize_attrs(&attrs, to_lowercase)
 }
 
+///
+/// Show `value` in an error message, values of hidden documents are masked
+/// so they do not get into logs and load reports.
+///
+fn show_value(value: &ParamValue, hidden: bool) -> String {
+    match hidden {
+        true => HIDDEN_VALUE_MASK.into(),
+        false => value.to_string(),
+    }
+}
+
+/// The most compare keys one level of an override order may expand into for a lookup.
+pub const MAX_COMPARE_KEYS: usize = 1024;
+
+///
+/// Get a hierarchical value and its ancestors, the most specific first.
+/// `base/web/prod` => `[base/web/prod, base/web, base]`
+///
+/// Slashes are kept as they are, so ancestors compare to override keys written the same way:
+/// `/base/web` => `[/base/web, /base]`
+///
+pub fn hierarchy_of(value: &str) -> Vec<String> {
+    let value = value.trim();
+    let mut items = vec![value.to_string()];
+    let mut current = value;
+    while let Some((parent, _)) = current.rsplit_once('/') {
+        if !parent.is_empty() {
+            items.push(parent.to_string());
+        }
+        current = parent;
+    }
+    items
+}
+
+///
+/// Build compare keys like `build_compare_key` does for every combination of attribute values
+/// and also keys for all ancestors of attributes listed in `hierarchical_attrs`.
+/// Keys are ordered from the most specific to the least specific one,
+/// keys of the same specificity keep the order of attribute values.
+///
+fn build_compare_keys(attrs: &Attrs, list_attrs: &Vec<String>, hierarchical_attrs: &[String]) -> Vec<String> {
+    // combinations of attribute values with their depth
+    let mut combinations: Vec<(usize, HashMap<String, String>)> = vec![(0, HashMap::new())];
+    for attr in list_attrs {
+        let values: Vec<(usize, String)> = attrs.values(attr).into_iter()
+            .flat_map(|value| match hierarchical_attrs.contains(attr) {
+                true => {
+                    let items = hierarchy_of(value);
+                    let total = items.len();
+                    items.into_iter().enumerate().map(|(i, it)| (total - i, it)).collect::<Vec<(usize, String)>>()
+                },
+                false => vec![(1, value.to_string())],
+            })
+            .collect();
+        combinations = combinations.into_iter()
+            .flat_map(|(depth, combination)| values.iter().map(move |(d, value)| {
+                let mut combination = combination.clone();
+                combination.insert(attr.clone(), value.clone());
+                (depth + d, combination)
+            }))
+            .collect();
+    }
+    // stable sort keeps the order of values for combinations with the same depth
+    combinations.sort_by(|(a, _), (b, _)| b.cmp(a));
+    let mut seen: HashSet<String> = HashSet::with_capacity(combinations.len());
+    let mut keys: Vec<String> = Vec::with_capacity(combinations.len());
+    for (_, combination) in combinations.iter() {
+        let key = build_compare_key(combination, list_attrs, true);
+        if seen.insert(key.clone()) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+///
+/// Count combinations `build_compare_keys` goes through for `attrs` without building them,
+/// so lookups which would expand into too many keys are rejected up front (see `MAX_COMPARE_KEYS`).
+///
+pub fn count_compare_keys(attrs: &Attrs, list_attrs: &[String], hierarchical_attrs: &[String]) -> usize {
+    list_attrs.iter()
+        .map(|attr| match hierarchical_attrs.contains(attr) {
+            true => attrs.values(attr).into_iter().map(|value| hierarchy_of(value).len()).sum(),
+            false => attrs.values(attr).len(),
+        })
+        .fold(1, usize::saturating_mul)
+}
+
 /*
 Parse order list into list of list of attributes
 Ex.,
@@ -396,9 +849,10 @@ where
 #[cfg(test)]
 mod test {
     use std::collections::HashMap;
-    use crate::documentv2::{
-        Document, normalize_override_key, normalize_attrs,
-        build_compare_key
+    use serde_json::json;
+    use super::{
+        Attrs, Document, DocumentValueType, MergeStrategy, normalize_override_key, normalize_attrs,
+        build_compare_key, build_compare_keys, build_overrides, hierarchy_of, OverrideItem
     };
 
     const DOC1_YAML: &str = r#"
@@ -431,11 +885,407 @@ mod test {
         assert_eq!(doc.total_overrides(), 2);
         assert_eq!(doc.default_value, String::from("Hello, World"));
         assert_eq!(doc.get_value(
-            &HashMap::<String, String>::from([
+            &Attrs::from(HashMap::<String, String>::from([
                 ("key2".into(), "value2".into()),
                 ("key3".into(), "value3".into()),
-            ])
-        ), "Hello, key2, key3");
+            ]))
+        ), Some("Hello, key2, key3".into()));
+    }
+
+    #[test]
+    fn test_explain() {
+        let doc = Document::try_from(DOC1_YAML).expect("could not parse document");
+        let trace = doc.explain(&Attrs::from(HashMap::<String, String>::from([
+            ("key2".into(), "value2".into()),
+            ("key3".into(), "value3".into()),
+        ])));
+        assert_eq!(trace.value, "Hello, key2, key3");
+        assert_eq!(trace.steps.len(), 2);
+        assert_eq!(trace.steps[0].compare_key, "key1=,key2=value2");
+        assert!(!trace.steps[0].matched);
+        assert_eq!(trace.steps[1].compare_key, "key2=value2,key3=value3");
+        assert!(trace.steps[1].matched);
+        assert!(!trace.default_used);
+
+        let trace = doc.explain(&Attrs::default());
+        assert_eq!(trace.value, "Hello, World");
+        assert!(trace.steps.iter().all(|step| !step.matched));
+        assert!(trace.default_used);
+    }
+
+    #[test]
+    fn test_override_disabled() {
+        let doc = Document::try_from(DOC1_YAML.replace("override: true", "override: false").as_str())
+            .expect("could not parse document");
+        let attrs = Attrs::from(HashMap::<String, String>::from([
+            ("key2".into(), "value2".into()),
+            ("key3".into(), "value3".into()),
+        ]));
+        assert_eq!(doc.get_value(&attrs), Some("Hello, World".into()));
+        let trace = doc.explain(&attrs);
+        assert!(!trace.overrides_enabled);
+        assert!(trace.steps.is_empty());
+        assert!(trace.default_used);
+        assert_eq!(trace.value, "Hello, World");
+    }
+
+    #[test]
+    fn test_omit() {
+        let attrs = Attrs::from(HashMap::<String, String>::from([
+            ("key1".into(), "value1".into()),
+            ("key2".into(), "value2".into()),
+        ]));
+        // the default value is omitted but a matched override is not
+        let doc = Document::try_from(DOC1_YAML.replace("omit: false\n    merge_default", "omit: true\n    merge_default").as_str())
+            .expect("could not parse document");
+        assert_eq!(doc.get_value(&attrs), Some("Hello, key1, key2".into()));
+        assert_eq!(doc.get_value(&Attrs::default()), None);
+        let trace = doc.explain(&Attrs::default());
+        assert!(trace.omitted);
+        assert!(trace.value.is_null());
+
+        // a matched omitted override means the parameter is not managed
+        let doc = Document::try_from(DOC1_YAML.replacen("omit: false\n        value: Hello, key1", "omit: true\n        value: Hello, key1", 1).as_str())
+            .expect("could not parse document");
+        assert_eq!(doc.get_value(&attrs), None);
+        assert_eq!(doc.get_value(&Attrs::default()), Some("Hello, World".into()));
+    }
+
+    const DOC_HASH_YAML: &str = r#"
+    description: Test hash document
+    default_value:
+      servers:
+        primary: default.example.com
+      timeout: 10
+    override: true
+    parameter_type: hash
+    parameter: settings
+    puppetclass_name: world
+    omit: false
+    merge_default: true
+    merge_overrides: true
+    override_values:
+      - match: fqdn=host.example.com
+        omit: false
+        value:
+          servers:
+            primary: host.example.com
+      - match: domain=example.com
+        omit: false
+        value:
+          servers:
+            primary: domain.example.com
+            secondary: backup.example.com
+          retries: 3
+    override_value_order:
+      - fqdn
+      - domain
+    hidden_value: false
+    validator_rule: null
+    validator_type: null
+    "#;
+
+    #[test]
+    fn test_merge_strategy() {
+        let attrs = Attrs::from(HashMap::<String, String>::from([
+            ("fqdn".into(), "host.example.com".into()),
+            ("domain".into(), "example.com".into()),
+        ]));
+        let doc = Document::try_from(DOC_HASH_YAML).expect("could not parse document");
+        assert_eq!(doc.merge_strategy(), MergeStrategy::Deep);
+        assert_eq!(doc.get_value(&attrs), Some(serde_json::json!({
+            "servers": { "primary": "host.example.com", "secondary": "backup.example.com" },
+            "timeout": 10,
+            "retries": 3,
+        })));
+
+        let doc = Document::try_from(format!("{}\n    merge_strategy: hash", DOC_HASH_YAML).as_str())
+            .expect("could not parse document");
+        assert_eq!(doc.merge_strategy(), MergeStrategy::Hash);
+        assert_eq!(doc.get_value(&attrs), Some(serde_json::json!({
+            "servers": { "primary": "host.example.com" },
+            "timeout": 10,
+            "retries": 3,
+        })));
+
+        let doc = Document::try_from(format!("{}\n    merge_strategy: first", DOC_HASH_YAML).as_str())
+            .expect("could not parse document");
+        assert_eq!(doc.get_value(&attrs), Some(serde_json::json!({
+            "servers": { "primary": "host.example.com" },
+        })));
+
+        let doc = Document::try_from(DOC_HASH_YAML.replace("merge_overrides: true", "merge_overrides: false").as_str())
+            .expect("could not parse document");
+        assert_eq!(doc.merge_strategy(), MergeStrategy::First);
+    }
+
+    const DOC_ARRAY_YAML: &str = r#"
+    description: Test array document
+    default_value:
+      - ntp.example.com
+    override: true
+    parameter_type: array
+    parameter: ntp_servers
+    puppetclass_name: world
+    omit: false
+    merge_default: true
+    merge_overrides: true
+    avoid_duplicates: true
+    override_values:
+      - match: fqdn=host.example.com
+        omit: false
+        value:
+          - ntp1.example.com
+          - ntp.example.com
+      - match: domain=example.com
+        omit: false
+        value:
+          - ntp2.example.com
+          - ntp1.example.com
+    override_value_order:
+      - fqdn
+      - domain
+    hidden_value: false
+    validator_rule: null
+    validator_type: null
+    "#;
+
+    #[test]
+    fn test_merge_arrays() {
+        let attrs = Attrs::from(HashMap::<String, String>::from([
+            ("fqdn".into(), "host.example.com".into()),
+            ("domain".into(), "example.com".into()),
+        ]));
+        let doc = Document::try_from(DOC_ARRAY_YAML).expect("could not parse document");
+        assert_eq!(doc.get_value(&attrs), Some(serde_json::json!([
+            "ntp1.example.com", "ntp.example.com", "ntp2.example.com",
+        ])));
+        assert_eq!(doc.get_value(&Attrs::default()), Some(serde_json::json!(["ntp.example.com"])));
+
+        let doc = Document::try_from(DOC_ARRAY_YAML.replace("avoid_duplicates: true", "avoid_duplicates: false").as_str())
+            .expect("could not parse document");
+        assert_eq!(doc.get_value(&attrs), Some(serde_json::json!([
+            "ntp1.example.com", "ntp.example.com", "ntp2.example.com", "ntp1.example.com", "ntp.example.com",
+        ])));
+
+        let doc = Document::try_from(DOC_ARRAY_YAML.replace("merge_default: true", "merge_default: false").as_str())
+            .expect("could not parse document");
+        assert_eq!(doc.get_value(&attrs), Some(serde_json::json!([
+            "ntp1.example.com", "ntp.example.com", "ntp2.example.com",
+        ])));
+
+        let doc = Document::try_from(DOC_ARRAY_YAML.replace("merge_overrides: true", "merge_overrides: false").as_str())
+            .expect("could not parse document");
+        assert_eq!(doc.get_value(&attrs), Some(serde_json::json!(["ntp1.example.com", "ntp.example.com"])));
+    }
+
+    #[test]
+    fn test_empty_values() {
+        let attrs = Attrs::from(HashMap::<String, String>::from([
+            ("fqdn".into(), "host.example.com".into()),
+            ("domain".into(), "example.com".into()),
+        ]));
+        let empty_hash = DOC_HASH_YAML
+            .replace("value:\n          servers:\n            primary: host.example.com", "value: {}")
+            .replace("value:\n          servers:\n            primary: domain.example.com\n            secondary: backup.example.com\n          retries: 3", "value: {}")
+            .replace("merge_default: true", "merge_default: false");
+        let doc = Document::try_from(empty_hash.as_str()).expect("could not parse document");
+        assert_eq!(doc.get_value(&attrs), Some(serde_json::json!({})));
+        assert_eq!(doc.get_value(&Attrs::default()), Some(doc.default_value.clone()));
+
+        let doc = Document::try_from(empty_hash.replace("merge_overrides: true", "merge_overrides: false").as_str())
+            .expect("could not parse document");
+        assert_eq!(doc.get_value(&attrs), Some(serde_json::json!({})));
+
+        let null_value = DOC1_YAML.replace("value: Hello, key2, key3", "value: null");
+        let doc = Document::try_from(null_value.as_str()).expect("could not parse document");
+        assert_eq!(doc.get_value(&Attrs::from(HashMap::<String, String>::from([
+            ("key2".into(), "value2".into()),
+            ("key3".into(), "value3".into()),
+        ]))), Some(serde_json::Value::Null));
+
+        let empty_array = DOC_ARRAY_YAML
+            .replace("value:\n          - ntp1.example.com\n          - ntp.example.com", "value: []")
+            .replace("value:\n          - ntp2.example.com\n          - ntp1.example.com", "value: []")
+            .replace("merge_default: true", "merge_default: false");
+        let doc = Document::try_from(empty_array.as_str()).expect("could not parse document");
+        assert_eq!(doc.get_value(&attrs), Some(serde_json::json!([])));
+    }
+
+    #[test]
+    fn test_cast_values() {
+        let cast = |value_type: DocumentValueType, value: serde_json::Value| value_type.cast(&value);
+        assert_eq!(cast(DocumentValueType::Boolean, json!("True")), Ok(json!(true)));
+        assert_eq!(cast(DocumentValueType::Boolean, json!("no")), Ok(json!(false)));
+        assert!(cast(DocumentValueType::Boolean, json!("maybe")).is_err());
+        assert_eq!(cast(DocumentValueType::Number, json!("42")), Ok(json!(42)));
+        assert_eq!(cast(DocumentValueType::Number, json!("4.5")), Ok(json!(4.5)));
+        assert!(cast(DocumentValueType::Number, json!([1])).is_err());
+        assert_eq!(cast(DocumentValueType::String, json!(42)), Ok(json!("42")));
+        assert!(cast(DocumentValueType::String, json!({"a": 1})).is_err());
+        assert_eq!(cast(DocumentValueType::Hash, json!("{a: 1}")), Ok(json!({"a": 1})));
+        assert!(cast(DocumentValueType::Hash, json!("[1, 2]")).is_err());
+        assert_eq!(cast(DocumentValueType::Array, json!("[1, 2]")), Ok(json!([1, 2])));
+        assert_eq!(cast(DocumentValueType::Json, json!("{\"a\": [1]}")), Ok(json!({"a": [1]})));
+        assert!(cast(DocumentValueType::Json, json!("{a: 1}")).is_err());
+        assert_eq!(cast(DocumentValueType::Yaml, json!("a: 1")), Ok(json!({"a": 1})));
+        assert_eq!(cast(DocumentValueType::Hash, json!("")), Ok(json!(null)));
+
+        let doc = Document::try_from(
+            DOC1_YAML
+                .replace("parameter_type: string", "parameter_type: boolean")
+                .replace("default_value: \"Hello, World\"", "default_value: \"true\"")
+                .replace("value: Hello, key1, key2", "value: \"no\"")
+                .replace("value: Hello, key2, key3", "value: 0")
+                .as_str()
+        ).expect("could not parse document");
+        assert_eq!(doc.default_value, json!(true));
+        assert_eq!(doc.get_value(&Attrs::from(HashMap::<String, String>::from([
+            ("key1".into(), "value1".into()),
+            ("key2".into(), "value2".into()),
+        ]))), Some(json!(false)));
+        assert!(Document::try_from(DOC1_YAML.replace("parameter_type: string", "parameter_type: number").as_str()).is_err());
+    }
+
+    #[test]
+    fn test_validators() {
+        let with_validator = |validator_type: &str, rule: &str| DOC1_YAML
+            .replace("validator_type: null", &format!("validator_type: {}", validator_type))
+            .replace("validator_rule: null", &format!("validator_rule: '{}'", rule));
+        assert!(Document::try_from(with_validator("regexp", "^Hello").as_str()).is_ok());
+        assert!(Document::try_from(with_validator("regexp", "key2$").as_str()).is_err());
+        assert!(Document::try_from(with_validator("regexp", "(").as_str()).is_err());
+        assert!(Document::try_from(
+            with_validator("list", "a, b")
+                .replace("default_value: \"Hello, World\"", "default_value: a")
+                .replace("value: Hello, key1, key2", "value: b")
+                .replace("value: Hello, key2, key3", "value: a")
+                .as_str()
+        ).is_ok());
+        assert!(Document::try_from(with_validator("list", "Hello").as_str()).is_err());
+        assert!(Document::try_from(with_validator("unknown", "").as_str()).is_err());
+        assert!(Document::try_from(with_validator("''", "").as_str()).is_ok());
+
+        // values of hidden documents do not get into errors
+        let hidden = |yaml: String| yaml.replace("hidden_value: false", "hidden_value: true");
+        let err = Document::try_from(with_validator("regexp", "key2$").as_str()).expect_err("bad value is not reported");
+        assert!(err.to_string().contains("Hello, World"));
+        let err = Document::try_from(hidden(with_validator("regexp", "key2$")).as_str()).expect_err("bad value is not reported");
+        assert!(! err.to_string().contains("Hello"), "{}", err);
+        let err = Document::try_from(hidden(DOC1_YAML.replace("parameter_type: string", "parameter_type: number")).as_str())
+            .expect_err("bad value is not reported");
+        assert!(! err.to_string().contains("Hello"), "{}", err);
+    }
+
+    #[test]
+    fn test_hierarchical_attrs() {
+        let attrs = Attrs::from(HashMap::<String, String>::from([
+            ("hostgroup".into(), "base/web/prod".into()),
+            ("domain".into(), "example.com".into()),
+        ]));
+        let keys = build_compare_keys(&attrs, &vec!["domain".into(), "hostgroup".into()], &["hostgroup".into()]);
+        assert_eq!(keys, vec![
+            "domain=example.com,hostgroup=base/web/prod",
+            "domain=example.com,hostgroup=base/web",
+            "domain=example.com,hostgroup=base",
+        ]);
+
+        let mut doc = Document::try_from(
+            DOC1_YAML
+                .replace("match: key1=value1,key2=value2", "match: key1=base/web")
+                .replace("match: key2=value2,key3=value3", "match: key1=base")
+                .replace("- key1,key2\n      - key2,key3", "- key1")
+                .as_str()
+        ).expect("could not parse document");
+        let lookup = |doc: &Document, hostgroup: &str| doc.get_value(&Attrs::from(&HashMap::from([("key1".into(), hostgroup.into())])));
+        assert_eq!(lookup(&doc, "base/web/prod"), Some("Hello, World".into()));
+        doc.hierarchical_attrs = vec!["key1".into()];
+        assert_eq!(lookup(&doc, "base/web/prod"), Some("Hello, key1, key2".into()));
+        assert_eq!(lookup(&doc, "base/web"), Some("Hello, key1, key2".into()));
+        assert_eq!(lookup(&doc, "base/db"), Some("Hello, key2, key3".into()));
+        assert_eq!(lookup(&doc, "other/web"), Some("Hello, World".into()));
+        assert_eq!(doc.explain(&Attrs::from(&HashMap::from([("key1".into(), "base/web/prod".into())]))).steps[0].compare_key, "key1=base/web");
+
+        assert_eq!(hierarchy_of("/base/web/"), vec!["/base/web/", "/base/web", "/base"]);
+        let mut doc = Document::try_from(
+            DOC1_YAML
+                .replace("match: key1=value1,key2=value2", "match: key1=/base/web")
+                .replace("match: key2=value2,key3=value3", "match: key1=/base")
+                .replace("- key1,key2\n      - key2,key3", "- key1")
+                .as_str()
+        ).expect("could not parse document");
+        doc.hierarchical_attrs = vec!["key1".into()];
+        assert_eq!(lookup(&doc, "/base/web/prod"), Some("Hello, key1, key2".into()));
+        assert_eq!(lookup(&doc, "/base/db"), Some("Hello, key2, key3".into()));
+        assert_eq!(lookup(&doc, "base/web/prod"), Some("Hello, World".into()));
+
+        // keys with patterns match descendants as well
+        let mut doc = Document::try_from(
+            DOC1_YAML
+                .replace("match: key1=value1,key2=value2", "match: key2 in (prod),key1=base/web")
+                .replace("match: key2=value2,key3=value3", "match: key2!=prod,key1=base")
+                .replace("\n      - key2,key3", "")
+                .as_str()
+        ).expect("could not parse document");
+        let lookup = |doc: &Document, hostgroup: &str, env: &str| doc.get_value(&Attrs::from(&HashMap::from([
+            ("key1".into(), hostgroup.into()),
+            ("key2".into(), env.into()),
+        ])));
+        assert_eq!(lookup(&doc, "base/web/prod", "prod"), Some("Hello, World".into()));
+        doc.hierarchical_attrs = vec!["key1".into()];
+        assert_eq!(lookup(&doc, "base/web", "prod"), Some("Hello, key1, key2".into()));
+        assert_eq!(lookup(&doc, "base/web/prod", "prod"), Some("Hello, key1, key2".into()));
+        assert_eq!(lookup(&doc, "base/web/prod", "dev"), Some("Hello, key2, key3".into()));
+        assert_eq!(lookup(&doc, "other/web", "prod"), Some("Hello, World".into()));
+    }
+
+    #[test]
+    fn test_pattern_overrides() {
+        let doc = Document::try_from(
+            DOC1_YAML
+                .replace("match: key1=value1,key2=value2", "match: key1=Web*.example.com")
+                .replace("match: key2=value2,key3=value3", "match: key1=~^db[0-9]{1,3}\\.")
+                .replace("- key1,key2\n      - key2,key3", "- key1")
+                .as_str()
+        ).expect("could not parse document");
+        assert_eq!(doc.patterns, vec!["key1=web*.example.com", "key1=~^db[0-9]{1,3}\\."]);
+        let lookup = |fqdn: &str| doc.get_value(&Attrs::from(&HashMap::from([("key1".into(), fqdn.into())])));
+        assert_eq!(lookup("web01.example.com"), Some("Hello, key1, key2".into()));
+        assert_eq!(lookup("DB12.example.com"), Some("Hello, key2, key3".into()));
+        assert_eq!(lookup("db1234.example.com"), Some("Hello, World".into()));
+        assert_eq!(lookup("app.example.com"), Some("Hello, World".into()));
+        let trace = doc.explain(&Attrs::from(&HashMap::from([("key1".into(), "web01.example.com".into())])));
+        assert_eq!(trace.steps[0].pattern, Some("key1=web*.example.com".into()));
+
+        assert!(Document::try_from(DOC1_YAML.replace("match: key1=value1,key2=value2", "match: key1=~(").as_str()).is_err());
+    }
+
+    #[test]
+    fn test_multi_valued_attrs() {
+        let doc = Document::try_from(
+            DOC1_YAML
+                .replace("match: key1=value1,key2=value2", "match: key1=web")
+                .replace("match: key2=value2,key3=value3", "match: key1!=db")
+                .replace("- key1,key2\n      - key2,key3", "- key1")
+                .as_str()
+        ).expect("could not parse document");
+        let lookup = |attrs: serde_json::Value| doc.get_value(&Attrs::try_from(attrs).expect("bad attributes"));
+        assert_eq!(lookup(json!({"key1": ["app", "web"]})), Some("Hello, key1, key2".into()));
+        assert_eq!(lookup(json!({"key1": ["app", "db"]})), Some("Hello, World".into()));
+        assert_eq!(lookup(json!({"key1": ["app"]})), Some("Hello, key2, key3".into()));
+
+        let doc = Document::try_from(
+            DOC1_YAML
+                .replace("match: key1=value1,key2=value2", "match: key1=true,key2=8")
+                .as_str()
+        ).expect("could not parse document");
+        assert_eq!(doc.get_value(&Attrs::try_from(json!({"key1": true, "key2": 8})).unwrap()), Some("Hello, key1, key2".into()));
+
+        assert!(Attrs::try_from(json!({"key1": "a,b"})).is_err());
+        assert!(Attrs::try_from(json!({"key1": "a=b"})).is_err());
+        assert!(Attrs::try_from(json!({"key1": {"a": 1}})).is_err());
+        assert!(Attrs::try_from(json!(["key1"])).is_err());
+        assert!(Attrs::from(HashMap::from([("key1".to_string(), "a,b".to_string())])).validate().is_err());
     }
 
     #[test]
@@ -455,15 +1305,24 @@ mod test {
     }
 
     #[test]
-    fn test_deser_overrides() {
+    fn test_build_overrides() {
         let source: &str = r#"
-        - key: key1=value1,key2=value2
+        - match: key1=value1,key2=value2
           omit: false
           value: Hello, World
-        - key: key3=value1,key3=value2
+        - match: key3=value1,key3=value2
           omit: false
           value: Hello, everyone
+        - match: KEY2=value2, key1=value1
+          omit: false
+          value: Bye
         "#;
+        let items: Vec<OverrideItem> = serde_yaml::from_str(source).unwrap();
+        let (overrides, duplicates) = build_overrides(items).expect("could not build overrides");
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(overrides["key1=value1,key2=value2"].value, serde_json::json!("Hello, World"));
+        assert_eq!(duplicates.len(), 1);
+        assert!(duplicates[0].contains("#3") && duplicates[0].contains("#1"));
     }
 
     #[test]
@@ -483,7 +1342,7 @@ mod test {
             ("key_z".into(), "   VALUE_2".into()),
             ("key_d".into(), "valUE_3  ".into()),
         ]);
-        let r1 = build_compare_key(&attrs, &vec!["key_a".into(), "key_z".into(), "a_key".into()], true);
+        let r1 = build_compare_key(&attrs, &["key_a".into(), "key_z".into(), "a_key".into()], true);
         assert_eq!(&r1, "a_key=,key_a=value_1,key_z=value_2");
     }
 }
@@ -1,14 +1,15 @@
-use std::{sync::Arc, collections::HashMap, str::FromStr};
+use std::{sync::Arc, collections::HashMap, str::FromStr, iter};
 use tokio::sync::RwLock;
 use super::{
     models,
     Collection, SharedCollection, CollectionError,
     document::{Document, DocumentError, DocumentOverrides},
+    attrs::Attrs,
+    reload,
 };
 use axum::{
     Json,
     Router,
-    RouterService,
     ServiceExt,
     http::{Request},
     routing::{get, IntoMakeService},
@@ -16,8 +17,91 @@ use axum::{
     extract::{Path, State, Query},
     response::{Result, Response, IntoResponse},
     middleware::{self, Next},
+    extract::FromRequestParts,
+    http::{StatusCode, request::Parts, header::AUTHORIZATION},
+    async_trait,
 };
 
+/// A header to ask for hidden values to be revealed.
+pub const REVEAL_HIDDEN_HEADER: &str = "x-reveal-hidden";
+
+#[async_trait]
+impl FromRequestParts<SharedCollection> for models::Visibility {
+    type Rejection = StatusCode;
+    ///
+    /// Hidden values are revealed only if a request asks for it with `X-Reveal-Hidden: true`
+    /// and is authorized with `Authorization: Bearer <reveal token>`.
+    /// A request asking for hidden values without a valid token is rejected.
+    ///
+    async fn from_request_parts(parts: &mut Parts, state: &SharedCollection) -> Result<Self, Self::Rejection> {
+        let reveal = parts.headers.get(REVEAL_HIDDEN_HEADER).and_then(|it| it.to_str().ok()).map(str::trim);
+        if ! reveal.is_some_and(|it| it.eq_ignore_ascii_case("true")) {
+            return Ok(models::Visibility::Masked);
+        }
+        authorize(parts, state.1.reveal_token.as_deref()).map(|_| models::Visibility::Revealed)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<SharedCollection> for models::AdminAccess {
+    type Rejection = StatusCode;
+    ///
+    /// The admin API is only open to requests with `Authorization: Bearer <admin token>`,
+    /// it is closed if the server runs without an admin token.
+    ///
+    async fn from_request_parts(parts: &mut Parts, state: &SharedCollection) -> Result<Self, Self::Rejection> {
+        authorize(parts, state.1.admin_token.as_deref()).map(|_| models::AdminAccess)
+    }
+}
+
+///
+/// Check `Authorization: Bearer <token>` of a request against an `expected` token,
+/// nothing is authorized if there is no `expected` token.
+///
+fn authorize(parts: &Parts, expected: Option<&str>) -> Result<(), StatusCode> {
+    let token = parts.headers.get(AUTHORIZATION)
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.strip_prefix("Bearer "))
+        .map(str::trim);
+    match (expected, token) {
+        (Some(expected), Some(token)) if token_eq(expected, token) => Ok(()),
+        (_, None) => Err(StatusCode::UNAUTHORIZED),
+        _ => Err(StatusCode::FORBIDDEN),
+    }
+}
+
+///
+/// Compare tokens in a time which depends on their length only, not on where they differ.
+///
+fn token_eq(expected: &str, token: &str) -> bool {
+    expected.len() == token.len()
+        && expected.bytes().zip(token.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// A header to turn strict lookups on or off for a request.
+pub const STRICT_LOOKUP_HEADER: &str = "x-strict-lookup";
+
+#[async_trait]
+impl FromRequestParts<SharedCollection> for models::LookupMode {
+    type Rejection = (StatusCode, String);
+    ///
+    /// A request may ask for a strict or lenient lookup with `X-Strict-Lookup: true|false`,
+    /// otherwise the mode of the server is used.
+    ///
+    async fn from_request_parts(parts: &mut Parts, state: &SharedCollection) -> Result<Self, Self::Rejection> {
+        let strict = match parts.headers.get(STRICT_LOOKUP_HEADER).map(|it| it.to_str().map(str::trim)) {
+            None => state.1.strict_lookup,
+            Some(Ok(value)) if value.eq_ignore_ascii_case("true") => true,
+            Some(Ok(value)) if value.eq_ignore_ascii_case("false") => false,
+            Some(_) => return Err((StatusCode::BAD_REQUEST, format!("{} must be true or false", STRICT_LOOKUP_HEADER))),
+        };
+        match strict {
+            true => Ok(models::LookupMode::Strict),
+            false => Ok(models::LookupMode::Lenient),
+        }
+    }
+}
+
 pub async fn remove_trailing_slash<B>(mut req: Request<B>, next: Next<B>) -> Response {
     *req.uri_mut() = http::uri::Uri::from_str(req.uri().path().trim_end_matches('/'))
         .unwrap_or_else(|_| req.uri().clone());
@@ -29,19 +113,23 @@ pub struct CollectionsStat {
     ping: &'static str,
     total_collections: usize,
     total_documents: usize,
+    bad_documents: usize,
 }
 
 pub async fn get_collections_stat(State(collections): State<SharedCollection>) -> Json<CollectionsStat> {
     let collections = &*collections.0.read().await;
     let (total_c, total_d) = (collections.total_collections(), collections.total_documents());
-    Json(CollectionsStat { ping: "pong", total_collections: total_c, total_documents: total_d })
+    let bad_documents = collections.report.bad_documents.len();
+    Json(CollectionsStat { ping: "pong", total_collections: total_c, total_documents: total_d, bad_documents })
 }
 
 
-pub async fn get_documents(Path(collection_name): Path<String>, State(collection): State<SharedCollection>)
+pub async fn get_documents(Path(collection_name): Path<String>,
+                       visibility: models::Visibility,
+                       State(collection): State<SharedCollection>)
     -> Result<models::CollectionResponse, models::CollectionResponse>
 {
-    models::CollectionInfo::try_from((&*collection.0.read().await, &collection_name))
+    models::CollectionInfo::try_from((&*collection.0.read().await, &collection_name, visibility))
         .map_or_else(
             |___| Err(models::CollectionResponse::CollectionNotFound(collection_name.clone())),
             |col| Ok(models::CollectionResponse::CollectionInfo(col))
@@ -50,14 +138,15 @@ pub async fn get_documents(Path(collection_name): Path<String>, State(collection
 
 /// Get a `DocumentInfo` by `collection_name` and `document_name`.
 pub async fn get_document(Path((collection_name, document_name)): Path<(String, String)>,
+                      visibility: models::Visibility,
                       State(collection): State<SharedCollection>)
     -> Result<models::CollectionResponse, models::CollectionResponse>
 {
-    (&*collection.0.read().await)
+    collection.0.read().await
         .get_document(&collection_name, &document_name)
         .map_or_else(
             |   | Err(models::CollectionResponse::DocumentNotFound(collection_name.clone(), document_name.clone())),
-            |doc| Ok(models::CollectionResponse::DocumentInfo(models::DocumentInfo::from(doc)))
+            |doc| Ok(models::CollectionResponse::DocumentInfo(Box::new(models::DocumentInfo::from((doc, visibility)))))
         )
 }
 
@@ -66,7 +155,7 @@ pub async fn get_document_attrs(Path((collection_name, document_name)): Path<(St
                             State(collection): State<SharedCollection>)
     -> Result<models::CollectionResponse, models::CollectionResponse>
 {
-    (&*collection.0.read().await)
+    collection.0.read().await
         .get_document(&collection_name, &document_name)
         .map_or_else(
             |   | Err(models::CollectionResponse::DocumentNotFound(collection_name.clone(), document_name.clone())),
@@ -74,17 +163,55 @@ pub async fn get_document_attrs(Path((collection_name, document_name)): Path<(St
         )
 }
 
-/// Lookup a `Document`'s value.
+/// Lookup a `Document`'s value or values of many documents given as a comma separated list.
 pub async fn get_document_value(Path((collection_name, document_name)): Path<(String, String)>,
-                            Query(query): Query<HashMap<String, String>>,
-                            State(collection): State<SharedCollection>)
+                            visibility: models::Visibility,
+                            mode: models::LookupMode,
+                            State(collection): State<SharedCollection>,
+                            attrs: Attrs)
     -> Result<models::CollectionResponse, models::CollectionResponse>
 {
-    (&*collection.0.read().await)
+    let collection = collection.0.read().await;
+    let attrs = collection.resolve_attrs(&attrs).map_err(models::CollectionResponse::from)?;
+    if document_name.contains(',') {
+        let names = document_name.split_terminator(',').map(|it| it.trim().to_lowercase()).collect::<Vec<String>>();
+        mode.check(collection.find_documents(Some(std::slice::from_ref(&collection_name)), Some(&names)), &attrs)
+            .map_err(models::CollectionResponse::MissingAttrs)?;
+        return models::DocumentsValues::try_from((&*collection, &collection_name, names.as_slice(), &attrs, visibility))
+            .map(models::CollectionResponse::DocumentsValues)
+            .map_err(models::CollectionResponse::from);
+    }
+    collection
         .get_document(&collection_name, &document_name)
         .map_or_else(
             |   | Err(models::CollectionResponse::DocumentNotFound(collection_name.clone(), document_name.clone())),
-            |doc| Ok(models::CollectionResponse::DocumentValue(doc.get_value(&query)))
+            |doc| {
+                mode.check(iter::once(doc), &attrs).map_err(models::CollectionResponse::MissingAttrs)?;
+                Ok(models::CollectionResponse::DocumentValue(models::DocumentValue::from((doc, doc.get_value(&attrs), visibility))))
+            }
+        )
+}
+
+///
+/// Lookup a `Document`'s value and explain how it was resolved.
+///
+pub async fn get_document_explain(Path((collection_name, document_name)): Path<(String, String)>,
+                              visibility: models::Visibility,
+                              mode: models::LookupMode,
+                              State(collection): State<SharedCollection>,
+                              attrs: Attrs)
+    -> Result<models::CollectionResponse, models::CollectionResponse>
+{
+    let collection = collection.0.read().await;
+    let attrs = collection.resolve_attrs(&attrs).map_err(models::CollectionResponse::from)?;
+    collection
+        .get_document(&collection_name, &document_name)
+        .map_or_else(
+            |   | Err(models::CollectionResponse::DocumentNotFound(collection_name.clone(), document_name.clone())),
+            |doc| {
+                mode.check(iter::once(doc), &attrs).map_err(models::CollectionResponse::MissingAttrs)?;
+                Ok(models::CollectionResponse::DocumentExplain(Box::new(models::DocumentExplain::from((doc, &attrs, visibility)))))
+            }
         )
 }
 
@@ -93,57 +220,176 @@ pub async fn get_document_value(Path((collection_name, document_name)): Path<(St
 ///
 pub async fn get_document_overrides(Path((collection_name, document_name)): Path<(String, String)>,
                                 Query(query): Query<HashMap<String, String>>,
+                                visibility: models::Visibility,
                                 State(collection): State<SharedCollection>)
     -> Result<models::CollectionResponse, models::CollectionResponse>
 {
-    (&*collection.0.read().await)
+    collection.0.read().await
         .get_document(&collection_name, &document_name)
         .map_or_else(
             |   | Err(models::CollectionResponse::DocumentNotFound(collection_name.clone(), document_name.clone())),
-            |doc| Ok(models::CollectionResponse::DocumentOverrides(models::DocumentOverrides::from(doc)))
+            |doc| Ok(models::CollectionResponse::DocumentOverrides(models::DocumentOverrides::from((doc, visibility))))
         )
 }
 
 /// Get a list of `CollectionInfo`.
-pub async fn get_collections(State(collection): State<SharedCollection>)
+pub async fn get_collections(visibility: models::Visibility, State(collection): State<SharedCollection>)
     -> Result<models::CollectionResponse, models::CollectionResponse>
 {
-    let collections: Vec<models::CollectionInfo> = (&*collection.0.read().await
+    let collections: Vec<models::CollectionInfo> = collection.0.read().await
         .documents
         .iter()
-        .map(|(name, documents)| models::CollectionInfo::from((documents, name)))
-        .collect::<Vec<models::CollectionInfo>>()).to_vec();
+        .map(|(name, documents)| models::CollectionInfo::from((documents, name, visibility)))
+        .collect();
 
     Ok(models::CollectionResponse::Collections(models::CollectionList::from(collections)))
 }
 
 ///
-/// Get a list of attributes from all documents found in the collection needed to look up values.
+/// Get a list of attributes from all documents found in the collection needed to look up values
+/// with their types, allowed values and descriptions from the schema.
 ///
 pub async fn get_collection_attrs(Path(collection_name): Path<String>, State(collection): State<SharedCollection>)
     -> Result<models::CollectionResponse, models::CollectionResponse>
 {
-    let collection_info = models::CollectionInfo::try_from((&*collection.0.read().await, &collection_name))
-        .map_err(|_| models::CollectionResponse::CollectionNotFound(collection_name.clone()))?;
-    Ok(models::CollectionResponse::CollectionAttrs(collection_info.attrs()))
+    let attrs = collection.0.read().await.get_attrs(&collection_name)
+        .ok_or_else(|| models::CollectionResponse::CollectionNotFound(collection_name.clone()))?;
+    Ok(models::CollectionResponse::CollectionAttrs(attrs))
 }
 
 pub async fn get_collection_values(Path(collection_name): Path<String>,
-                               Query(query): Query<HashMap<String, String>>,
-                               State(collection): State<SharedCollection>)
+                               visibility: models::Visibility,
+                               mode: models::LookupMode,
+                               State(collection): State<SharedCollection>,
+                               attrs: Attrs)
     -> Result<models::CollectionResponse, models::CollectionResponse>
 {
-    (&*collection.0.read().await).get_values(&collection_name, &query)
-        .map_or_else(
-            || Err(models::CollectionResponse::CollectionNotFound(collection_name.clone())),
-            |values| Ok(models::CollectionResponse::CollectionValues(values))
-        )
+    let collection = collection.0.read().await;
+    let attrs = collection.resolve_attrs(&attrs).map_err(models::CollectionResponse::from)?;
+    mode.check(collection.find_documents(Some(std::slice::from_ref(&collection_name)), None), &attrs)
+        .map_err(models::CollectionResponse::MissingAttrs)?;
+    let values = models::CollectionValues::try_from((&*collection, &collection_name, &attrs, visibility))
+        .map_err(|_| models::CollectionResponse::CollectionNotFound(collection_name.clone()))?;
+    Ok(models::CollectionResponse::CollectionValues(values))
 }
 
-pub async fn get_collection(Path(collection_name): Path<String>, State(collection): State<SharedCollection>)
+pub async fn get_collection(Path(collection_name): Path<String>,
+                        visibility: models::Visibility,
+                        State(collection): State<SharedCollection>)
     -> Result<models::CollectionResponse, models::CollectionResponse>
 {
-    let info = models::CollectionInfo::try_from((&*collection.0.read().await, &collection_name))
+    let info = models::CollectionInfo::try_from((&*collection.0.read().await, &collection_name, visibility))
         .map_err(|_| models::CollectionResponse::CollectionNotFound(collection_name.clone()))?;
     Ok(models::CollectionResponse::CollectionInfo(info))
 }
+
+///
+/// Look up values for many attribute sets under a single read lock.
+///
+pub async fn post_lookup(visibility: models::Visibility,
+                     mode: models::LookupMode,
+                     State(collection): State<SharedCollection>,
+                     Json(batch): Json<models::BatchLookup>)
+    -> Result<models::CollectionResponse, models::CollectionResponse>
+{
+    let collection = collection.0.read().await;
+    let resolved = batch.resolve(&collection, mode)?;
+    models::BatchValues::try_from((&*collection, &batch, resolved.as_slice(), visibility))
+        .map(models::CollectionResponse::BatchValues)
+        .map_err(models::CollectionResponse::from)
+}
+
+///
+/// Get a Puppet External Node Classifier document for a host with name `fqdn`.
+/// Other attributes of the host are taken from the query or a JSON body.
+///
+pub async fn get_node_classification(Path(fqdn): Path<String>,
+                                 visibility: models::Visibility,
+                                 mode: models::LookupMode,
+                                 State(collection): State<SharedCollection>,
+                                 mut attrs: Attrs)
+    -> Result<models::CollectionResponse, models::CollectionResponse>
+{
+    attrs.insert("fqdn", &fqdn);
+    attrs.validate().map_err(models::CollectionResponse::BadAttrs)?;
+    let collection = collection.0.read().await;
+    let attrs = collection.resolve_attrs(&attrs).map_err(models::CollectionResponse::from)?;
+    mode.check(collection.find_documents(None, None), &attrs).map_err(models::CollectionResponse::MissingAttrs)?;
+    let node = models::NodeClassification::try_from((&*collection, &attrs, visibility))
+        .map_err(models::CollectionResponse::from)?;
+    Ok(models::CollectionResponse::NodeClassification(node))
+}
+
+///
+/// Look up values of all collections.
+///
+pub async fn get_values(visibility: models::Visibility,
+                    mode: models::LookupMode,
+                    State(collection): State<SharedCollection>,
+                    attrs: Attrs)
+    -> Result<models::CollectionResponse, models::CollectionResponse>
+{
+    let collection = collection.0.read().await;
+    let attrs = collection.resolve_attrs(&attrs).map_err(models::CollectionResponse::from)?;
+    mode.check(collection.find_documents(None, None), &attrs).map_err(models::CollectionResponse::MissingAttrs)?;
+    models::CollectionsValues::try_from((&*collection, None, &attrs, visibility))
+        .map(models::CollectionResponse::CollectionsValues)
+        .map_err(models::CollectionResponse::from)
+}
+
+///
+/// Look up values of collections given as a comma separated list of names.
+///
+pub async fn get_collections_values(Path(collection_names): Path<String>,
+                                visibility: models::Visibility,
+                                mode: models::LookupMode,
+                                State(collection): State<SharedCollection>,
+                                attrs: Attrs)
+    -> Result<models::CollectionResponse, models::CollectionResponse>
+{
+    let names = collection_names.split_terminator(',').map(String::from).collect::<Vec<String>>();
+    let collection = collection.0.read().await;
+    let attrs = collection.resolve_attrs(&attrs).map_err(models::CollectionResponse::from)?;
+    let lowercased = names.iter().map(|it| it.trim().to_lowercase()).collect::<Vec<String>>();
+    mode.check(collection.find_documents(Some(&lowercased), None), &attrs)
+        .map_err(models::CollectionResponse::MissingAttrs)?;
+    models::CollectionsValues::try_from((&*collection, Some(&names), &attrs, visibility))
+        .map(models::CollectionResponse::CollectionsValues)
+        .map_err(models::CollectionResponse::from)
+}
+
+///
+/// Load the collection directory again, the current collection is kept if it fails.
+///
+pub async fn post_reload(_: models::AdminAccess, State(collection): State<SharedCollection>)
+    -> Result<models::CollectionResponse, models::CollectionResponse>
+{
+    match reload::reload(&collection).await {
+        Ok(summary) => Ok(models::CollectionResponse::Reload(summary)),
+        Err(err) => Err(models::CollectionResponse::Reload(models::ReloadSummary::from(&err))),
+    }
+}
+
+///
+/// Get the report of the last load of the collection directory.
+///
+pub async fn get_load_report(_: models::AdminAccess, State(collection): State<SharedCollection>)
+    -> Result<models::CollectionResponse, models::CollectionResponse>
+{
+    let report = collection.0.read().await.report.clone();
+    let failure = collection.2.read().await.clone();
+    Ok(models::CollectionResponse::LoadAttempt(Box::new(models::LoadAttempt::from((report, failure)))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::token_eq;
+
+    #[test]
+    fn test_token_eq() {
+        assert!(token_eq("secret", "secret"));
+        assert!(! token_eq("secret", "secreT"));
+        assert!(! token_eq("secret", "secret1"));
+        assert!(! token_eq("secret", ""));
+    }
+}
@@ -0,0 +1,335 @@
+///
+///
+/// The module provides matchers for override keys with patterns.
+///
+/// An override key is a comma separated list of conditions `attr<op>value`:
+/// - `fqdn=host.example.com` the value equals
+/// - `fqdn=web*.example.com` the value matches a wildcard, `*` matches any characters, `?` matches one
+/// - `fqdn=~^db[0-9]+\.` the value matches a regular expression (case insensitive)
+/// - `is_virtual!=true` the value does not equal (or does not match a wildcard)
+/// - `env in (prod,stage)`, `env not in (dev,test)` the value is (not) one of listed values
+/// - `os_release>=8`, `>`, `<=`, `<` comparisons, dotted values are compared as versions,
+///   i.e. `8.10` is greater than `8.9`
+///
+/// Commas inside brackets are not treated as separators, so `fqdn=~^db[0-9]{1,3}$` is a single condition.
+///
+///
+use regex::{Regex, RegexBuilder};
+use std::cmp::Ordering;
+use super::{attrs::Attrs, document::hierarchy_of};
+
+#[derive(Debug, Clone)]
+pub enum Condition {
+    /// `attr=value`
+    Equal(String),
+    /// `attr=web*.example.com`
+    Wildcard(String, Regex),
+    /// `attr=~^db[0-9]+\.`
+    Regex(Regex),
+    /// `attr in (a,b)`
+    In(Vec<String>),
+    /// `attr>=8`, the operator and the value
+    Compare(String, String),
+    /// `attr!=value`, `attr not in (a,b)`
+    Not(Box<Condition>),
+}
+
+///
+/// Compare two values as numbers if both are numbers without dots, otherwise as versions.
+/// Version components which are not numbers are compared as strings.
+///
+fn compare_values(a: &str, b: &str) -> Ordering {
+    if let (false, Ok(x), Ok(y)) = (a.contains('.') || b.contains('.'), a.parse::<i64>(), b.parse::<i64>()) {
+        return x.cmp(&y);
+    }
+    let (a, b): (Vec<&str>, Vec<&str>) = (a.split('.').collect(), b.split('.').collect());
+    for i in 0..a.len().max(b.len()) {
+        let (x, y) = (a.get(i).copied().unwrap_or("0"), b.get(i).copied().unwrap_or("0"));
+        let order = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => x.cmp(y),
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    Ordering::Equal
+}
+
+impl Condition {
+    ///
+    /// Check a lowercased and trimmed attribute value.
+    ///
+    pub fn is_match(&self, value: &str) -> bool {
+        match self {
+            Condition::Equal(expected) => expected == value,
+            Condition::Wildcard(_, re) | Condition::Regex(re) => re.is_match(value),
+            Condition::In(values) => values.iter().any(|it| it == value),
+            Condition::Compare(op, expected) => {
+                // a missing attribute is never compared
+                if value.is_empty() {
+                    return false;
+                }
+                let order = compare_values(value, expected);
+                match op.as_str() {
+                    ">=" => order != Ordering::Less,
+                    "<=" => order != Ordering::Greater,
+                    ">" => order == Ordering::Greater,
+                    _ => order == Ordering::Less,
+                }
+            },
+            Condition::Not(condition) => ! condition.is_match(value),
+        }
+    }
+
+    pub fn is_pattern(&self) -> bool {
+        ! matches!(self, Condition::Equal(_))
+    }
+
+    fn render(&self, attr: &str) -> String {
+        match self {
+            Condition::Equal(value) | Condition::Wildcard(value, _) => format!("{}={}", attr, value),
+            Condition::Regex(re) => format!("{}=~{}", attr, re.as_str()),
+            Condition::In(values) => format!("{} in ({})", attr, values.join(",")),
+            Condition::Compare(op, value) => format!("{}{}{}", attr, op, value),
+            Condition::Not(condition) => match condition.as_ref() {
+                Condition::In(_) => condition.render(attr).replacen(" in ", " not in ", 1),
+                condition => condition.render(attr).replacen('=', "!=", 1),
+            },
+        }
+    }
+
+    ///
+    /// Parse a condition `attr<op>value` into an attribute name and a condition.
+    /// An item without an operator is an equality to an empty value.
+    ///
+    fn parse(item: &str) -> Result<(String, Self), String> {
+        if let Some(parsed) = Self::parse_in(item) {
+            return parsed;
+        }
+        let item = item.trim();
+        let i = match item.find(['=', '!', '<', '>']) {
+            Some(i) => i,
+            None => return Ok((item.to_lowercase(), Condition::Equal("".into()))),
+        };
+        let (attr, expr) = (item[..i].trim().to_lowercase(), &item[i..]);
+        let condition = if let Some(value) = expr.strip_prefix("!=") {
+            Condition::Not(Box::new(Condition::try_from(value)?))
+        } else if let Some(op) = [">=", "<=", ">", "<"].iter().find(|op| expr.starts_with(*op)) {
+            let value = expr[op.len()..].trim().to_lowercase();
+            if value.is_empty() {
+                return Err(format!("no value to compare {} with", &attr));
+            }
+            Condition::Compare(op.to_string(), value)
+        } else if let Some(value) = expr.strip_prefix('=') {
+            Condition::try_from(value)?
+        } else {
+            return Err(format!("unknown operator in {}", item));
+        };
+        Ok((attr, condition))
+    }
+
+    ///
+    /// Parse `attr in (a,b)` and `attr not in (a,b)` conditions.
+    /// An attribute name with an operator, i.e. `attr=x in (a,b)`, is an error,
+    /// except for a regular expression `attr=~re` which may contain ` in (...)` itself.
+    ///
+    fn parse_in(item: &str) -> Option<Result<(String, Self), String>> {
+        let item = item.trim().to_lowercase();
+        let (attr, rest) = item.split_once(char::is_whitespace)?;
+        let (negated, rest) = match rest.trim_start().strip_prefix("not") {
+            Some(rest) if rest.starts_with(char::is_whitespace) => (true, rest.trim_start()),
+            _ => (false, rest.trim_start()),
+        };
+        let values = rest.strip_prefix("in")?.trim().strip_prefix('(')?.strip_suffix(')')?;
+        if attr.contains(['=', '!', '<', '>']) {
+            return match attr.contains('~') {
+                true => None,
+                false => Some(Err(format!("unknown operator in {}", item))),
+            };
+        }
+        let values = values.split(',').map(|it| it.trim().to_string()).filter(|it| ! it.is_empty()).collect();
+        let condition = Condition::In(values);
+        Some(Ok((attr.to_string(), match negated {
+            true => Condition::Not(Box::new(condition)),
+            false => condition,
+        })))
+    }
+}
+
+impl TryFrom<&str> for Condition {
+    type Error = String;
+    ///
+    /// Parse a value of an equality condition, i.e. everything after `attr=`.
+    /// Whitespace around the operator is ignored, so `attr = ~re` is a regular expression as well.
+    ///
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let compile = |pattern: &str| RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|err| format!("bad pattern {}: {}", pattern, err));
+        match value.trim_start().strip_prefix('~') {
+            Some(pattern) => Ok(Condition::Regex(compile(pattern.trim())?)),
+            None => {
+                let value = value.trim().to_lowercase();
+                match value.contains(['*', '?']) {
+                    true => {
+                        let pattern = regex::escape(&value).replace("\\*", ".*").replace("\\?", ".");
+                        let re = compile(&format!("^{}$", pattern))?;
+                        Ok(Condition::Wildcard(value, re))
+                    },
+                    false => Ok(Condition::Equal(value)),
+                }
+            },
+        }
+    }
+}
+
+///
+/// A parsed override key.
+///
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    /// conditions sorted by attribute names
+    conditions: Vec<(String, Condition)>,
+    /// sorted attribute names
+    attrs: Vec<String>,
+}
+
+impl Matcher {
+    pub fn attrs(&self) -> &Vec<String> {
+        &self.attrs
+    }
+
+    pub fn conditions(&self) -> &Vec<(String, Condition)> {
+        &self.conditions
+    }
+
+    ///
+    /// A matcher is a pattern if any of its conditions is not an equality.
+    /// Other matchers are looked up by their normalized keys.
+    ///
+    pub fn is_pattern(&self) -> bool {
+        self.conditions.iter().any(|(_, condition)| condition.is_pattern())
+    }
+
+    ///
+    /// Check all conditions against `attrs`. Missing attributes have empty values.
+    /// A condition on an attribute with many values holds if any of the values matches,
+    /// a negated condition holds if none of the values matches.
+    /// Values of attributes listed in `hierarchical_attrs` come with their ancestors,
+    /// so `hostgroup=base/web` matches `base/web/prod` like an exact key does.
+    ///
+    pub fn is_match(&self, attrs: &Attrs, hierarchical_attrs: &[String]) -> bool {
+        self.conditions.iter().all(|(attr, condition)| {
+            let values = attrs.values(attr).into_iter()
+                .map(|it| it.trim().to_lowercase())
+                .flat_map(|it| match hierarchical_attrs.contains(attr) {
+                    true => hierarchy_of(&it),
+                    false => vec![it],
+                })
+                .collect::<Vec<String>>();
+            match condition {
+                Condition::Not(condition) => ! values.iter().any(|value| condition.is_match(value)),
+                condition => values.iter().any(|value| condition.is_match(value)),
+            }
+        })
+    }
+
+    ///
+    /// Get a normalized key of the matcher.
+    ///
+    pub fn key(&self) -> String {
+        self.conditions.iter()
+            .map(|(attr, condition)| condition.render(attr))
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+impl TryFrom<&str> for Matcher {
+    type Error = String;
+    fn try_from(key: &str) -> Result<Self, Self::Error> {
+        let mut conditions = Vec::new();
+        for item in split_conditions(key) {
+            let condition = Condition::parse(item).map_err(|err| format!("matcher {}: {}", key, err))?;
+            conditions.push(condition);
+        }
+        conditions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut attrs = conditions.iter().map(|(attr, _)| attr.clone()).collect::<Vec<String>>();
+        attrs.dedup();
+        Ok(Self { conditions, attrs })
+    }
+}
+
+///
+/// Split a key into conditions by commas which are not inside brackets.
+/// Characters escaped with `\`, i.e. `\[` in a regular expression, are not brackets or separators.
+///
+pub fn split_conditions(key: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let (mut depth, mut start, mut escaped) = (0_i32, 0, false);
+    for (i, c) in key.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth <= 0 => {
+                items.push(&key[start..i]);
+                start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    items.push(&key[start..]);
+    items.into_iter().filter(|it| ! it.trim().is_empty()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use super::{Attrs, Matcher};
+
+    #[test]
+    fn test_conditions() {
+        let attrs = Attrs::from(HashMap::<String, String>::from([
+            ("env".into(), "Prod".into()),
+            ("os_release".into(), "8.10".into()),
+            ("is_virtual".into(), "false".into()),
+            ("fqdn".into(), "db01.example.com".into()),
+        ]));
+        let tests: Vec<(&str, bool, &str)> = vec![
+            ("env in (prod, stage)", true, "env in (prod,stage)"),
+            ("env not in (prod,stage)", false, "env not in (prod,stage)"),
+            ("ENV IN (dev)", false, "env in (dev)"),
+            ("is_virtual!=true", true, "is_virtual!=true"),
+            ("is_virtual!=f*", false, "is_virtual!=f*"),
+            ("os_release>=8", true, "os_release>=8"),
+            ("os_release>8.9", true, "os_release>8.9"),
+            ("os_release<8.2", false, "os_release<8.2"),
+            ("os_release<=8.10", true, "os_release<=8.10"),
+            ("missing>=1", false, "missing>=1"),
+            ("fqdn=~^db[0-9]{1,3}\\.,env=prod", true, "env=prod,fqdn=~^db[0-9]{1,3}\\."),
+            ("env in (prod),os_release>=9", false, "env in (prod),os_release>=9"),
+            ("fqdn = ~^DB0", true, "fqdn=~^DB0"),
+            ("fqdn != ~^web", true, "fqdn!=~^web"),
+            ("fqdn=~^db\\[?01,env=prod", true, "env=prod,fqdn=~^db\\[?01"),
+            ("fqdn=~^db\\]?01,env=dev", false, "env=dev,fqdn=~^db\\]?01"),
+        ];
+        for (key, expected, normalized) in tests {
+            let matcher = Matcher::try_from(key).expect("could not parse matcher");
+            assert!(matcher.is_pattern(), "{}", key);
+            assert_eq!(matcher.is_match(&attrs, &[]), expected, "{}", key);
+            assert_eq!(matcher.key(), normalized);
+        }
+        assert!(! Matcher::try_from("env=prod,fqdn=db01").unwrap().is_pattern());
+        assert!(Matcher::try_from("os_release>=").is_err());
+        assert!(Matcher::try_from("env=prod in (stage)").is_err());
+        assert!(Matcher::try_from("env!=prod not in (stage)").is_err());
+        assert_eq!(Matcher::try_from("fqdn=~^db in (a|b)").unwrap().key(), "fqdn=~^db in (a|b)");
+    }
+}
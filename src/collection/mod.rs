@@ -1,8 +1,19 @@
 mod models;
+mod matcher;
+mod attrs;
+mod pipeline;
+mod schema;
 mod document;
+#[allow(clippy::module_inception)]
 mod collection;
 pub mod handlers;
-pub use self::collection::{Collection, CollectionError};
+pub mod reload;
+#[cfg(test)]
+mod testing;
+pub use self::collection::{Collection, CollectionError, CollectionOptions};
+pub use self::document::MergeStrategy;
+pub use self::attrs::{Attrs, DELIMITERS};
+pub use self::models::{AttrsReport, LookupMode, NodeClassification, Visibility};
 
 use std::{sync::Arc, collections::HashMap, str::FromStr};
 use tokio::sync::RwLock;
@@ -10,10 +21,9 @@ use tower::{Layer, ServiceBuilder};
 use axum::{
     Json,
     Router,
-    RouterService,
     ServiceExt,
     http::{Request},
-    routing::{get, IntoMakeService},
+    routing::{get, post, IntoMakeService},
     // handler::Handler,
     extract::{Path, State, Query},
     response::{Result, Response, IntoResponse},
@@ -21,12 +31,46 @@ use axum::{
 };
 use self::document::{DocumentError, DocumentOverrides};
 
+///
+/// The collection in use, settings of the API, a failure of the last load if it failed
+/// and the state of reloads of the collection.
+///
 #[derive(Clone)]
-pub struct SharedCollection(Arc<RwLock<Collection>>);
+pub struct SharedCollection(
+    Arc<RwLock<Collection>>,
+    Arc<ApiSettings>,
+    Arc<RwLock<Option<models::LoadFailure>>>,
+    Arc<reload::ReloadState>,
+);
+
+///
+/// Settings of the collection API.
+///
+#[derive(Debug, Clone, Default)]
+pub struct ApiSettings {
+    /// a token to reveal hidden values, hidden values are always masked if not set
+    pub reveal_token: Option<String>,
+    /// reject lookups missing attributes used by overrides unless a request asks otherwise
+    pub strict_lookup: bool,
+    /// a token to use the admin API, the admin API is closed if not set
+    pub admin_token: Option<String>,
+}
 
 impl From<Collection> for SharedCollection {
     fn from(collection: Collection) -> Self {
-        Self(Arc::new(RwLock::new(collection)))
+        Self::from((collection, ApiSettings::default()))
+    }
+}
+
+impl From<(Collection, ApiSettings)> for SharedCollection {
+    fn from(item: (Collection, ApiSettings)) -> Self {
+        let (collection, settings) = item;
+        Self(
+            Arc::new(RwLock::new(collection)),
+            Arc::new(settings),
+            Arc::new(RwLock::new(None)),
+            Arc::new(reload::ReloadState::default()),
+        )
     }
 }
 
@@ -36,10 +80,27 @@ impl From<Collection> for SharedCollection {
 /// /collection
 /// /collection/<name>
 /// /collection/<name>/attrs        get attributes needed to look up values of all documents from the collection
+///                                 `[{"name": ..., "type": ..., "values": [...], "pattern": ..., "description": ...}]`
 /// /collection/<name>/values       look up values from documents in the collection
 /// /collection/<name>/document
 /// /collection/<name>/document/<name>/value
-/// /collection/<name>/document/<name1,name2,...>/value
+/// /collection/<name>/document/<name>/explain   look up a value and show how it was resolved
+/// /collection/<name>/document/<name1,name2,...>/value   `{"values": {document: value}, "unknown": [...]}`
+///
+/// Lookups take attributes from a query. `POST` variants of lookups (`values`, `value`, `explain`)
+/// take attributes as a JSON object with strings, numbers, booleans and lists of them.
+/// Attributes go through the pipeline of aliases and derived attributes declared in `.takeit.yml`
+/// of the collection directory before values are looked up.
+///
+/// If `.takeit.yml` declares a schema of attributes, lookups with unknown or malformed attributes
+/// are rejected with `400`.
+///
+/// A lookup with `X-Strict-Lookup: true` (or any lookup if the server runs with `--strict-lookup`
+/// and the header is not `false`) missing attributes used by overrides of looked up documents
+/// is rejected with `400 {"missing": [...], "ignored": [...]}`.
+///
+/// Values of documents with `hidden_value` set are masked unless a request has
+/// `X-Reveal-Hidden: true` and `Authorization: Bearer <reveal token>` headers.
 ///
 pub fn collection_router() -> Router<SharedCollection> {
     let router = Router::new() // with_state(collection)
@@ -47,16 +108,70 @@ pub fn collection_router() -> Router<SharedCollection> {
         .route("/stat", get(handlers::get_collections_stat))
         .route("/:collection_name", get(handlers::get_collection))
         .route("/:collection_name/attrs", get(handlers::get_collection_attrs))
-        .route("/:collection_name/values", get(handlers::get_collection_values))
+        .route("/:collection_name/values", get(handlers::get_collection_values).post(handlers::get_collection_values))
         .route("/:collection_name/document", get(handlers::get_documents))
         .route("/:collection_name/document/:document_name", get(handlers::get_document))
         .route("/:collection_name/document/:document_name/attrs", get(handlers::get_document_attrs))
-        .route("/:collection_name/document/:document_name/value", get(handlers::get_document_value))
+        .route("/:collection_name/document/:document_name/value", get(handlers::get_document_value).post(handlers::get_document_value))
+        .route("/:collection_name/document/:document_name/explain", get(handlers::get_document_explain).post(handlers::get_document_explain))
         .route("/:collection_name/document/:document_name/overrides", get(handlers::get_document_overrides));
     tracing::info!("collection API initialized");
     router
 }
 
+///
+/// Lookup API
+///
+/// `GET` lookups take attributes from a query, `POST` lookups take them as a JSON object.
+///
+/// POST /lookup    look up values for many attribute sets at once, at most `models::MAX_BATCH` sets
+///                 `{"attrs": [{...}, ...], "collections": [...], "documents": [...]}`
+/// GET /values   look up values of all collections, `{collection: {document: value}}`
+/// GET /values/<name1,name2,...>   look up values of the listed collections
+/// GET /enc/<fqdn> get a Puppet External Node Classifier YAML document for a host,
+///                 other attributes of the host are given in the query,
+///                 hidden values must be revealed as masked values are never given to Puppet (403 otherwise)
+///
+pub fn lookup_router() -> Router<SharedCollection> {
+    let router = Router::new()
+        .route("/lookup", post(handlers::post_lookup))
+        .route("/values", get(handlers::get_values).post(handlers::get_values))
+        .route("/values/:collection_names", get(handlers::get_collections_values).post(handlers::get_collections_values))
+        .route("/enc/:fqdn", get(handlers::get_node_classification).post(handlers::get_node_classification));
+    tracing::info!("lookup API initialized");
+    router
+}
+
+///
+/// Admin API
+///
+/// Requests must have `Authorization: Bearer <admin token>`, otherwise they are rejected
+/// with `401` or `403`. All requests are rejected if the server runs without `--admin-token`.
+///
+/// POST /admin/reload  load the collection directory again and swap it in if it loads,
+///                     `{"reloaded": true, "added": [...], "removed": [...], "modified": [...], "errors": []}`,
+///                     the current collection is kept and `500` is returned if it fails,
+///                     `{"reloaded": false, "errors": [...], "bad_document": {"path": ..., "line": 1, ...}, ...}`.
+///                     A reload requested while another one is queued is not run, `202 {"queued": true, ...}`
+///                     is returned as the queued reload picks up the changes.
+///                     `SIGHUP` reloads the collection directory as well.
+/// GET /admin/load-report  get the report of the last load, documents which could not be loaded
+///                     with `--ignore-bad-documents` are listed with their errors,
+///                     `{"status": "loaded", "loaded_at": ..., "elapsed_ms": ..., "total_files": ..., "total_documents": ..., "threads": ...,
+///                       "bad_documents": [{"path": ..., "kind": "parse", "message": ..., "line": 3, "column": 1, "elapsed_ms": ...}],
+///                       "duplicates": [{"path": ..., "kind": "duplicate", "duplicate_of": ..., ...}]}`,
+///                     duplicates are listed if they are kept with `--ignore-duplicates`.
+///                     If the last reload failed, the failure and the report of the collection in use
+///                     are returned, `{"status": "failed", "failed_at": ..., "error": ..., "bad_document": {...}, "current": {...}}`
+///
+pub fn admin_router() -> Router<SharedCollection> {
+    let router = Router::new()
+        .route("/reload", post(handlers::post_reload))
+        .route("/load-report", get(handlers::get_load_report));
+    tracing::info!("admin API initialized");
+    router
+}
+
 pub async fn remove_trailing_slash<B>(mut req: Request<B>, next: Next<B>) -> Response {
     *req.uri_mut() = http::uri::Uri::from_str(req.uri().path().trim_end_matches('/'))
         .unwrap_or_else(|_| req.uri().clone());
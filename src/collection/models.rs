@@ -5,19 +5,108 @@
 ///
 ///
 use super::{
-    document::{Document, ParamValue, DocumentOverrides as DocOverrides, DocumentValueType},
-    collection::{Collection, CollectionError},
+    document::{Document, ParamValue, DocumentOverrides as DocOverrides, DocumentValueType, LookupTrace, MergeStrategy},
+    collection::{BadDocument, Collection, CollectionError, LoadReport},
+    attrs::Attrs,
+    schema::AttrSchema,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use axum::{
     Json,
     response::{Response, IntoResponse},
-    http::StatusCode
+    http::{StatusCode, header},
 };
-use std::collections::HashMap;
+use std::{collections::{HashMap, BTreeMap, BTreeSet}, iter, time::SystemTime};
 
+/// A value shown instead of a hidden value.
+pub const HIDDEN_VALUE_MASK: &str = "*****";
+
+///
+/// Whether values of documents with `hidden_value` set are shown as is or masked.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Visibility {
+    #[default]
+    Masked,
+    Revealed,
+}
+
+impl Visibility {
+    ///
+    /// Get a value of the `document` to be shown.
+    /// `null` values are not masked as there is nothing to hide.
+    ///
+    pub fn show(&self, document: &Document, value: &ParamValue) -> ParamValue {
+        match *self == Visibility::Masked && document.is_hidden() && ! value.is_null() {
+            true => ParamValue::String(HIDDEN_VALUE_MASK.into()),
+            false => value.clone(),
+        }
+    }
+}
+
+///
+/// A request authorized to use the admin API.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct AdminAccess;
+
+///
+/// Whether lookups which miss attributes used by overrides of looked up documents are rejected.
+/// Otherwise missing attributes have empty values.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LookupMode {
+    #[default]
+    Lenient,
+    Strict,
+}
+
+impl LookupMode {
+    ///
+    /// Check `attrs` of a lookup of `documents`. A strict lookup missing any attribute
+    /// used by overrides of the documents is rejected with a report.
+    ///
+    pub fn check<'a>(&self, documents: impl IntoIterator<Item = &'a Document>, attrs: &Attrs) -> Result<(), AttrsReport> {
+        let report = AttrsReport::from((documents, attrs));
+        match *self == LookupMode::Strict && ! report.missing.is_empty() {
+            true => Err(report),
+            false => Ok(()),
+        }
+    }
+}
+
+///
+/// Attributes used by overrides of looked up documents which a lookup does not have (`missing`)
+/// and attributes of the lookup which no looked up document uses (`ignored`).
+/// Documents with disabled overrides do not use any attributes.
+///
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AttrsReport {
+    missing: Vec<String>,
+    ignored: Vec<String>,
+    /// an index of the attribute set in a batch lookup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    item: Option<usize>,
+}
+
+///
+/// A looked up value. An omitted value is `null` and has `omitted` set,
+/// so it can be told apart from a `null` value.
+///
 #[derive(Clone, Serialize)]
-pub struct DocumentValue(ParamValue);
+pub struct DocumentValue {
+    value: ParamValue,
+    omitted: bool,
+}
+
+///
+/// Looked up values of many documents and names of documents which were not found.
+///
+#[derive(Clone, Serialize)]
+pub struct DocumentsValues {
+    values: HashMap<String, DocumentValue>,
+    unknown: Vec<String>,
+}
 
 #[derive(Clone, Serialize)]
 pub struct DocumentAttrs(Vec<String>);
@@ -25,9 +114,114 @@ pub struct DocumentAttrs(Vec<String>);
 #[derive(Clone, Serialize)]
 pub struct DocumentOverrides(DocOverrides);
 
+#[derive(Clone, Serialize)]
+pub struct DocumentExplain {
+    collection: String,
+    document: String,
+    attrs: Attrs,
+    value: ParamValue,
+    omitted: bool,
+    #[serde(flatten)]
+    attrs_report: AttrsReport,
+    trace: LookupTrace,
+}
+
+#[derive(Clone, Serialize)]
+pub struct CollectionValues(HashMap<String, ParamValue>);
+
+/// The most attribute sets one batch lookup may have, the collection is locked for the whole batch.
+pub const MAX_BATCH: usize = 256;
+
+///
+/// A request to look up values for many attribute sets at once.
+/// Only `collections` and `documents` are looked up if given.
+///
+#[derive(Clone, Deserialize)]
+pub struct BatchLookup {
+    attrs: Vec<Attrs>,
+    collections: Option<Vec<String>>,
+    documents: Option<Vec<String>>,
+}
+
+///
+/// Values of many collections, keys are collection names.
+///
+#[derive(Clone, Serialize)]
+pub struct CollectionsValues(HashMap<String, CollectionValues>);
+
+#[derive(Clone, Serialize)]
+pub struct BatchItem {
+    attrs: Attrs,
+    values: CollectionsValues,
+}
+
+#[derive(Clone, Serialize)]
+pub struct BatchValues(Vec<BatchItem>);
+
+///
+/// A Puppet External Node Classifier document, collections are classes
+/// and values of their documents are class parameters.
+///
+#[derive(Clone, Serialize)]
+pub struct NodeClassification {
+    classes: BTreeMap<String, BTreeMap<String, ParamValue>>,
+}
+
+///
+/// Changes of documents made by a reload, documents are named `collection/document`.
+/// A failed reload has `reloaded` unset and `errors`, the current collection is kept then.
+/// If a document failed the reload, it is given in `bad_document` with a position of the error.
+/// A reload with `ignore_bad_documents` set lists documents which could not be loaded in `errors`.
+/// A reload requested while another one is waiting for the running one is not run, it has `queued` set
+/// as the waiting reload picks up the changes.
+///
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReloadSummary {
+    reloaded: bool,
+    queued: bool,
+    added: Vec<String>,
+    removed: Vec<String>,
+    modified: Vec<String>,
+    errors: Vec<String>,
+    /// a document which failed the reload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bad_document: Option<Box<BadDocument>>,
+}
+
+///
+/// A failed load of the collection directory, the current collection is kept.
+///
+#[derive(Clone, Debug, Serialize)]
+pub struct LoadFailure {
+    /// when loading failed, seconds since the Unix epoch
+    failed_at: u64,
+    error: String,
+    /// a document which failed the load
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bad_document: Option<Box<BadDocument>>,
+}
+
+///
+/// The last attempt to load the collection directory.
+///
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum LoadAttempt {
+    /// the collection in use was loaded by the last attempt
+    Loaded(LoadReport),
+    /// the last attempt failed, `current` is the report of the collection in use
+    Failed {
+        #[serde(flatten)]
+        failure: LoadFailure,
+        current: LoadReport,
+    },
+}
+
 #[derive(Clone, Serialize)]
 pub struct DocumentInfo {
     enabled: bool,
+    omit: bool,
+    hidden_value: bool,
     document: String,
     collection: String,
     description: String,
@@ -35,6 +229,8 @@ pub struct DocumentInfo {
     override_order: Vec<String>,
     default_value: ParamValue,
     value_type: DocumentValueType,
+    merge_strategy: MergeStrategy,
+    avoid_duplicates: bool,
 }
 
 #[derive(Clone, Serialize)]
@@ -51,6 +247,58 @@ pub struct CollectionList {
     collections: Vec<CollectionInfo>,
 }
 
+impl<'a, I: IntoIterator<Item = &'a Document>> From<(I, &Attrs)> for AttrsReport {
+    fn from(request: (I, &Attrs)) -> Self {
+        let (documents, attrs) = request;
+        let used = documents.into_iter()
+            .filter(|doc| doc.enabled)
+            .flat_map(|doc| doc.override_attrs())
+            .collect::<BTreeSet<String>>();
+        let mut ignored = attrs.names().into_iter()
+            .filter(|it| ! used.contains(*it))
+            .cloned()
+            .collect::<Vec<String>>();
+        ignored.sort();
+        Self {
+            missing: used.iter().filter(|it| ! attrs.contains(it)).cloned().collect(),
+            ignored,
+            item: None,
+        }
+    }
+}
+
+impl From<(&Document, Option<ParamValue>, Visibility)> for DocumentValue {
+    fn from(request: (&Document, Option<ParamValue>, Visibility)) -> Self {
+        let (document, value, visibility) = request;
+        Self {
+            omitted: value.is_none(),
+            value: visibility.show(document, &value.unwrap_or_default()),
+        }
+    }
+}
+
+impl TryFrom<(&Collection, &String, &[String], &Attrs, Visibility)> for DocumentsValues {
+    type Error = CollectionError;
+    ///
+    /// Look up values of documents `document_names` in the collection `collection_name`.
+    /// Unknown documents are listed in `unknown`.
+    ///
+    fn try_from(request: (&Collection, &String, &[String], &Attrs, Visibility)) -> Result<Self, Self::Error> {
+        let (collection, collection_name, document_names, attrs, visibility) = request;
+        collection.get_documents(collection_name)
+            .ok_or_else(|| CollectionError::CollectionNotFound(collection_name.clone()))?;
+        let mut this = Self { values: HashMap::new(), unknown: Vec::new() };
+        for name in document_names {
+            let name = name.trim().to_lowercase();
+            match collection.get_document(collection_name, &name) {
+                Some(doc) => { this.values.insert(name, DocumentValue::from((doc, doc.get_value(attrs), visibility))); },
+                None => this.unknown.push(name),
+            }
+        }
+        Ok(this)
+    }
+}
+
 impl From<&Document> for DocumentAttrs {
     fn from(document: &Document) -> Self {
         Self(document.override_attrs())
@@ -59,21 +307,292 @@ impl From<&Document> for DocumentAttrs {
 
 impl From<&Document> for DocumentOverrides {
     fn from(document: &Document) -> Self {
-        Self(document.get_overrides())
+        Self::from((document, Visibility::default()))
+    }
+}
+
+impl From<(&Document, Visibility)> for DocumentOverrides {
+    fn from(request: (&Document, Visibility)) -> Self {
+        let (document, visibility) = request;
+        let mut overrides = document.get_overrides();
+        overrides.values_mut().for_each(|it| it.value = visibility.show(document, &it.value));
+        Self(overrides)
+    }
+}
+
+impl From<(&Document, &Attrs, Visibility)> for DocumentExplain {
+    fn from(request: (&Document, &Attrs, Visibility)) -> Self {
+        let (document, attrs, visibility) = request;
+        let mut trace = document.explain(attrs);
+        trace.initial_value = visibility.show(document, &trace.initial_value);
+        trace.value = visibility.show(document, &trace.value);
+        trace.steps.iter_mut().for_each(|step| {
+            step.value = step.value.as_ref().map(|value| visibility.show(document, value));
+        });
+        Self {
+            collection: document.collection.clone(),
+            document: document.name.clone(),
+            attrs: attrs.clone(),
+            value: trace.value.clone(),
+            omitted: trace.omitted,
+            attrs_report: AttrsReport::from((iter::once(document), attrs)),
+            trace,
+        }
+    }
+}
+
+impl TryFrom<(&Collection, &String, &Attrs, Visibility)> for CollectionValues {
+    type Error = CollectionError;
+    ///
+    /// Look up values of all documents in the collection with name `collection_name`.
+    ///
+    fn try_from(request: (&Collection, &String, &Attrs, Visibility)) -> Result<Self, Self::Error> {
+        let (collection, collection_name, attrs, visibility) = request;
+        Self::lookup(collection, collection_name, attrs, None, visibility)
+            .ok_or_else(|| CollectionError::CollectionNotFound(collection_name.clone()))
+    }
+}
+
+impl CollectionValues {
+    ///
+    /// Look up values of documents with names `documents` or all documents if `None`.
+    ///
+    fn lookup(collection: &Collection, collection_name: &String, attrs: &Attrs,
+              documents: Option<&Vec<String>>, visibility: Visibility) -> Option<Self>
+    {
+        let mut values = match documents {
+            Some(names) => collection.get_document_values(collection_name, names, attrs)?,
+            None => collection.get_values(collection_name, attrs)?,
+        };
+        collection.get_documents(collection_name)?.iter().for_each(|doc| {
+            if let Some(value) = values.get_mut(&doc.name) {
+                *value = visibility.show(doc, value);
+            }
+        });
+        Some(Self(values))
+    }
+}
+
+impl CollectionsValues {
+    ///
+    /// Get lowercased collection names or names of all collections if `None`.
+    /// `CollectionError::CollectionNotFound` is returned if any of collections does not exist.
+    ///
+    fn names(collection: &Collection, names: Option<&Vec<String>>) -> Result<Vec<String>, CollectionError> {
+        let names = match names {
+            Some(names) => names.iter().map(|it| it.trim().to_lowercase()).collect::<Vec<String>>(),
+            None => collection.documents.keys().cloned().collect(),
+        };
+        match names.iter().find(|name| collection.get_documents(name).is_none()) {
+            Some(name) => Err(CollectionError::CollectionNotFound(name.clone())),
+            None => Ok(names),
+        }
+    }
+
+    fn lookup(collection: &Collection, names: &[String], attrs: &Attrs,
+              documents: Option<&Vec<String>>, visibility: Visibility) -> Self
+    {
+        Self(names.iter()
+            .filter_map(|name| {
+                CollectionValues::lookup(collection, name, attrs, documents, visibility)
+                    .map(|values| (name.clone(), values))
+            })
+            .collect())
+    }
+}
+
+impl TryFrom<(&Collection, Option<&Vec<String>>, &Attrs, Visibility)> for CollectionsValues {
+    type Error = CollectionError;
+    ///
+    /// Look up values of collections with names `names` or all collections if `None`.
+    ///
+    fn try_from(request: (&Collection, Option<&Vec<String>>, &Attrs, Visibility)) -> Result<Self, Self::Error> {
+        let (collection, names, attrs, visibility) = request;
+        let names = Self::names(collection, names)?;
+        Ok(Self::lookup(collection, &names, attrs, None, visibility))
+    }
+}
+
+impl BatchLookup {
+    ///
+    /// Prepare every attribute set of the batch for lookups (see `Collection::resolve_attrs`)
+    /// and check it, the first rejected one fails the batch. So does a batch of more than `MAX_BATCH` sets.
+    ///
+    pub fn resolve(&self, collection: &Collection, mode: LookupMode) -> Result<Vec<Attrs>, CollectionResponse> {
+        if self.attrs.len() > MAX_BATCH {
+            return Err(CollectionResponse::BadAttrs(
+                format!("{} attribute sets in a batch, at most {} are allowed", self.attrs.len(), MAX_BATCH)
+            ));
+        }
+        let collections = self.collections.as_ref()
+            .map(|names| names.iter().map(|it| it.trim().to_lowercase()).collect::<Vec<String>>());
+        let documents = self.documents.as_ref()
+            .map(|names| names.iter().map(|it| it.trim().to_lowercase()).collect::<Vec<String>>());
+        let documents = collection.find_documents(collections.as_deref(), documents.as_deref());
+        let mut resolved = Vec::with_capacity(self.attrs.len());
+        for (i, attrs) in self.attrs.iter().enumerate() {
+            let attrs = collection.resolve_attrs(attrs).map_err(CollectionResponse::from)?;
+            mode.check(documents.iter().copied(), &attrs)
+                .map_err(|report| CollectionResponse::MissingAttrs(AttrsReport { item: Some(i), ..report }))?;
+            resolved.push(attrs);
+        }
+        Ok(resolved)
+    }
+}
+
+impl TryFrom<(&Collection, &BatchLookup, &[Attrs], Visibility)> for BatchValues {
+    type Error = CollectionError;
+    ///
+    /// Look up values for every attribute set of the `BatchLookup` with the attribute sets `resolved`
+    /// by `BatchLookup::resolve`. Items keep attributes as they were given, before the attribute pipeline.
+    /// `CollectionError::CollectionNotFound` is returned if any of requested collections does not exist.
+    ///
+    fn try_from(request: (&Collection, &BatchLookup, &[Attrs], Visibility)) -> Result<Self, Self::Error> {
+        let (collection, batch, resolved, visibility) = request;
+        let collections = CollectionsValues::names(collection, batch.collections.as_ref())?;
+        let documents = batch.documents.as_ref()
+            .map(|names| names.iter().map(|it| it.trim().to_lowercase()).collect::<Vec<String>>());
+        let items = batch.attrs.iter().zip(resolved).map(|(attrs, resolved)| BatchItem {
+            attrs: attrs.clone(),
+            values: CollectionsValues::lookup(collection, &collections, resolved, documents.as_ref(), visibility),
+        }).collect();
+        Ok(Self(items))
+    }
+}
+
+impl TryFrom<(&Collection, &Attrs, Visibility)> for NodeClassification {
+    type Error = CollectionError;
+    ///
+    /// Look up values of all collections for a host with attributes `attrs`.
+    /// Puppet would apply a masked value as it is, so hidden values are never masked here.
+    /// `CollectionError::HiddenValues` is returned instead unless hidden values are revealed.
+    ///
+    fn try_from(request: (&Collection, &Attrs, Visibility)) -> Result<Self, Self::Error> {
+        let (collection, attrs, visibility) = request;
+        let classes: BTreeMap<String, BTreeMap<String, ParamValue>> = collection.documents.keys()
+            .filter_map(|name| {
+                CollectionValues::lookup(collection, name, attrs, None, Visibility::Revealed)
+                    .map(|values| (name.clone(), values.0.into_iter().collect()))
+            })
+            .collect();
+        let hidden = classes.iter()
+            .flat_map(|(class, params)| params.iter().map(move |(param, value)| (class, param, value)))
+            .filter(|(class, param, value)| {
+                collection.get_document(class, param).is_some_and(|doc| visibility.show(doc, value) != **value)
+            })
+            .map(|(class, param, _)| format!("{}/{}", class, param))
+            .collect::<Vec<String>>();
+        match hidden.is_empty() {
+            true => Ok(Self { classes }),
+            false => Err(CollectionError::HiddenValues(hidden)),
+        }
+    }
+}
+
+impl From<(&Collection, &Collection)> for ReloadSummary {
+    ///
+    /// Compare the current collection with a reloaded one.
+    ///
+    fn from(request: (&Collection, &Collection)) -> Self {
+        let (current, reloaded) = request;
+        let (before, after) = (current.checksums(), reloaded.checksums());
+        Self {
+            reloaded: true,
+            queued: false,
+            bad_document: None,
+            added: after.keys().filter(|it| ! before.contains_key(*it)).cloned().collect(),
+            removed: before.keys().filter(|it| ! after.contains_key(*it)).cloned().collect(),
+            modified: after.iter()
+                .filter(|(name, checksum)| before.get(*name).is_some_and(|it| it != *checksum))
+                .map(|(name, _)| name.clone())
+                .collect(),
+            errors: reloaded.report.bad_documents.iter()
+                .map(|it| format!("{}: {}", it.path.display(), &it.message))
+                .collect(),
+        }
+    }
+}
+
+impl From<&CollectionError> for ReloadSummary {
+    fn from(err: &CollectionError) -> Self {
+        let bad_document = match err {
+            CollectionError::BadDocument(bad) => Some(bad.clone()),
+            _ => None,
+        };
+        Self { errors: vec![err.to_string()], bad_document, ..Default::default() }
+    }
+}
+
+impl From<&CollectionError> for LoadFailure {
+    fn from(err: &CollectionError) -> Self {
+        Self {
+            failed_at: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |it| it.as_secs()),
+            error: err.to_string(),
+            bad_document: match err {
+                CollectionError::BadDocument(bad) => Some(bad.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl From<(LoadReport, Option<LoadFailure>)> for LoadAttempt {
+    ///
+    /// Get the last attempt from the report of the collection in use and a failure of a later load.
+    ///
+    fn from(item: (LoadReport, Option<LoadFailure>)) -> Self {
+        match item {
+            (current, Some(failure)) => LoadAttempt::Failed { failure, current },
+            (report, None) => LoadAttempt::Loaded(report),
+        }
+    }
+}
+
+impl ReloadSummary {
+    ///
+    /// A reload which joined a reload waiting for the running one.
+    ///
+    pub fn queued() -> Self {
+        Self { queued: true, ..Default::default() }
+    }
+
+    pub fn is_reloaded(&self) -> bool {
+        self.reloaded
+    }
+
+    pub fn is_queued(&self) -> bool {
+        self.queued
+    }
+}
+
+impl NodeClassification {
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        serde_yaml::to_string(self)
     }
 }
 
 impl From<&Document> for DocumentInfo {
     fn from(document: &Document) -> Self {
+        Self::from((document, Visibility::default()))
+    }
+}
+
+impl From<(&Document, Visibility)> for DocumentInfo {
+    fn from(request: (&Document, Visibility)) -> Self {
+        let (document, visibility) = request;
         Self {
             document: document.name.clone(),
             collection: document.collection.clone(),
             description: document.description.clone(),
             enabled: document.enabled,
+            omit: document.omit,
+            hidden_value: document.is_hidden(),
             total_overrides: document.total_overrides(),
-            default_value: document.default_value.clone(),
+            default_value: visibility.show(document, &document.default_value),
             override_order: document.override_order(),
             value_type: document.value_type.clone(),
+            merge_strategy: document.merge_strategy(),
+            avoid_duplicates: document.avoid_duplicates,
         }
     }
 }
@@ -83,7 +602,7 @@ impl TryFrom<(&Collection, &String, &String)> for DocumentInfo {
     fn try_from(input: (&Collection, &String, &String)) -> Result<Self, Self::Error> {
         let (collection, collection_name, document_name) = input;
         let doc = collection
-            .get_document(&collection_name, &document_name)
+            .get_document(collection_name, document_name)
             .ok_or_else(|| CollectionError::DocumentNotFound(collection_name.clone(), document_name.clone()))?;
         Ok(DocumentInfo::from(doc))
     }
@@ -95,51 +614,50 @@ impl TryFrom<(&Collection, &String, &String)> for DocumentInfo {
 impl From<(&Vec<Document>, &String)> for CollectionInfo {
     fn from(request: (&Vec<Document>, &String)) -> Self {
         let (documents, collection_name) = request;
+        Self::from((documents, collection_name, Visibility::default()))
+    }
+}
+
+impl From<(&Vec<Document>, &String, Visibility)> for CollectionInfo {
+    fn from(request: (&Vec<Document>, &String, Visibility)) -> Self {
+        let (documents, collection_name, visibility) = request;
         Self {
             collection: collection_name.clone(),
             total_documents: documents.len(),
-            documents: documents.iter().map(|d| DocumentInfo::from(d)).collect::<Vec<DocumentInfo>>(),
+            documents: documents.iter().map(|d| DocumentInfo::from((d, visibility))).collect::<Vec<DocumentInfo>>(),
         }
     }
 }
 
 impl TryFrom<(&Collection, &String)> for CollectionInfo {
+    type Error = CollectionError;
+    fn try_from(request: (&Collection, &String)) -> Result<Self, Self::Error> {
+        let (collection, collection_name) = request;
+        Self::try_from((collection, collection_name, Visibility::default()))
+    }
+}
+
+impl TryFrom<(&Collection, &String, Visibility)> for CollectionInfo {
     type Error = CollectionError;
     ///
     /// Get a collection's documents or return `CollectionError::CollectionNotFound`
     /// if the collection does not exist.
     ///
-    fn try_from(request: (&Collection, &String)) -> Result<Self, Self::Error> {
-        let (collection, collection_name) = request;
-        let documents = collection.get_documents(&collection_name)
+    fn try_from(request: (&Collection, &String, Visibility)) -> Result<Self, Self::Error> {
+        let (collection, collection_name, visibility) = request;
+        let documents = collection.get_documents(collection_name)
                             .ok_or_else(|| CollectionError::CollectionNotFound(collection_name.clone()))?
                             .iter()
-                            .map(|doc| DocumentInfo::from(doc))
+                            .map(|doc| DocumentInfo::from((doc, visibility)))
                             .collect::<Vec<DocumentInfo>>();
         Ok(Self {
             collection: collection_name.clone(),
             total_documents: documents.len(),
-            documents: documents,
+            documents,
         })
     }
 }
 
-impl CollectionInfo {
-    ///
-    /// Get a list of attributes which found from documents in the collection.
-    ///
-    pub fn attrs(&self) -> Vec<String> {
-        let mut attrs = std::collections::HashSet::<&str>::new();
-        self.documents.iter().for_each(|doc| {
-            doc.override_order.iter().for_each(|order_item|
-                order_item.split_terminator(',')
-                    .for_each(|attr| { attrs.insert(attr); })
-            )
-        });
-        attrs.iter().map(|it| (*it).into()).collect::<Vec<String>>()
-    }
-}
-
 impl From<Vec<CollectionInfo>> for CollectionList {
     fn from(list: Vec<CollectionInfo>) -> Self {
         Self {
@@ -151,16 +669,39 @@ impl From<Vec<CollectionInfo>> for CollectionList {
 }
 
 pub enum CollectionResponse {
-    DocumentInfo(DocumentInfo),
-    DocumentValue(ParamValue),
+    DocumentInfo(Box<DocumentInfo>),
+    DocumentValue(DocumentValue),
+    DocumentsValues(DocumentsValues),
+    DocumentExplain(Box<DocumentExplain>),
     DocumentAttrs(DocumentAttrs),
     DocumentNotFound(String, String),   // collection name, document name
     DocumentOverrides(DocumentOverrides),      // document overrides
     CollectionInfo(CollectionInfo),
-    CollectionAttrs(Vec<String>),       // list of attributes to look up values from all documents in the collection
-    CollectionValues(HashMap<String, ParamValue>),
+    CollectionAttrs(Vec<AttrSchema>),   // list of attributes to look up values from all documents in the collection
+    CollectionValues(CollectionValues),
+    CollectionsValues(CollectionsValues),
+    BatchValues(BatchValues),
+    NodeClassification(NodeClassification),
     Collections(CollectionList),   // all collections
     CollectionNotFound(String),         // collection name
+    MissingAttrs(AttrsReport),          // a strict lookup misses attributes
+    BadAttrs(String),                   // attributes of a lookup are unknown or malformed
+    Forbidden(String),                  // a request may not get what it asks for, i.e. hidden values
+    Reload(ReloadSummary),
+    LoadAttempt(Box<LoadAttempt>),
+    Error(String),                      // any other error
+}
+
+impl From<CollectionError> for CollectionResponse {
+    fn from(err: CollectionError) -> Self {
+        match err {
+            CollectionError::DocumentNotFound(collection, document) => CollectionResponse::DocumentNotFound(collection, document),
+            CollectionError::CollectionNotFound(collection) => CollectionResponse::CollectionNotFound(collection),
+            CollectionError::AttrsError(err) => CollectionResponse::BadAttrs(err),
+            err @ CollectionError::HiddenValues(_) => CollectionResponse::Forbidden(err.to_string()),
+            err => CollectionResponse::Error(err.to_string()),
+        }
+    }
 }
 
 impl IntoResponse for CollectionResponse {
@@ -168,14 +709,188 @@ impl IntoResponse for CollectionResponse {
         match self {
             CollectionResponse::DocumentInfo(info) => (StatusCode::OK, Json(info)).into_response(),
             CollectionResponse::DocumentValue(value) => (StatusCode::OK, Json(value)).into_response(),
+            CollectionResponse::DocumentsValues(values) => (StatusCode::OK, Json(values)).into_response(),
+            CollectionResponse::DocumentExplain(explain) => (StatusCode::OK, Json(explain)).into_response(),
             CollectionResponse::DocumentAttrs(attrs) => (StatusCode::OK, Json(attrs)).into_response(),
             CollectionResponse::DocumentOverrides(overrides) => (StatusCode::OK, Json(overrides)).into_response(),
             CollectionResponse::DocumentNotFound(_, _) => (StatusCode::NOT_FOUND).into_response(),
             CollectionResponse::CollectionInfo(info) => (StatusCode::OK, Json(info)).into_response(),
             CollectionResponse::CollectionAttrs(attrs) => (StatusCode::OK, Json(attrs)).into_response(),
             CollectionResponse::CollectionValues(values) => (StatusCode::OK, Json(values)).into_response(),
+            CollectionResponse::CollectionsValues(values) => (StatusCode::OK, Json(values)).into_response(),
+            CollectionResponse::BatchValues(values) => (StatusCode::OK, Json(values)).into_response(),
+            CollectionResponse::NodeClassification(node) => match node.to_yaml() {
+                Ok(yaml) => (StatusCode::OK, [(header::CONTENT_TYPE, "application/x-yaml")], yaml).into_response(),
+                Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+            },
             CollectionResponse::Collections(collections) => (StatusCode::OK, Json(collections)).into_response(),
             CollectionResponse::CollectionNotFound(_) => (StatusCode::NOT_FOUND).into_response(),
+            CollectionResponse::MissingAttrs(report) => (StatusCode::BAD_REQUEST, Json(report)).into_response(),
+            CollectionResponse::BadAttrs(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+            CollectionResponse::Forbidden(err) => (StatusCode::FORBIDDEN, err).into_response(),
+            CollectionResponse::LoadAttempt(attempt) => (StatusCode::OK, Json(attempt)).into_response(),
+            CollectionResponse::Reload(summary) => match (summary.is_reloaded(), summary.is_queued()) {
+                (true, _) => (StatusCode::OK, Json(summary)).into_response(),
+                (false, true) => (StatusCode::ACCEPTED, Json(summary)).into_response(),
+                (false, false) => (StatusCode::INTERNAL_SERVER_ERROR, Json(summary)).into_response(),
+            },
+            CollectionResponse::Error(err) => (StatusCode::INTERNAL_SERVER_ERROR, err).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use std::{collections::HashMap, iter};
+    use axum::{http::StatusCode, response::IntoResponse};
+    use super::{
+        Attrs, BatchLookup, BatchValues, Collection, CollectionError, CollectionResponse, CollectionsValues, Document,
+        DocumentInfo, DocumentOverrides, DocumentsValues, LookupMode, NodeClassification, Visibility, HIDDEN_VALUE_MASK,
+        MAX_BATCH,
+    };
+    use crate::collection::testing::{doc_yaml, DOC_YAML};
+
+    ///
+    /// A document with a hidden default value `secret` and an override of `fqdn=host.example.com`.
+    ///
+    fn hidden_doc() -> Document {
+        let yaml = doc_yaml(&[("fqdn=host.example.com", "host-secret")], &["fqdn"])
+            .replace("default_value: default", "default_value: secret")
+            .replace("hidden_value: false", "hidden_value: true");
+        Document::try_from(yaml.as_str()).expect("could not parse document")
+    }
+
+    ///
+    /// A collection `world` with a hidden document `hello` and a document `bye`,
+    /// and a collection `ntp` with a document `servers` which value is omitted for `host.example.com`.
+    ///
+    fn collection() -> Collection {
+        let bye = doc_yaml(&[("fqdn=host.example.com", "host-bye")], &["fqdn"]).replace("parameter: hello", "parameter: bye");
+        let servers = doc_yaml(&[("fqdn=other.example.com", "other")], &["fqdn"])
+            .replace("parameter: hello", "parameter: servers")
+            .replace("puppetclass_name: world", "puppetclass_name: ntp");
+        let omitted = doc_yaml(&[], &["fqdn"])
+            .replace("override_values:\n", "override_values:\n  - match: fqdn=host.example.com\n    omit: true\n    value: null\n")
+            .replace("parameter: hello", "parameter: pool")
+            .replace("puppetclass_name: world", "puppetclass_name: ntp");
+        let parse = |yaml: &str| Document::try_from(yaml).expect("could not parse document");
+        Collection {
+            documents: HashMap::from([
+                ("world".into(), vec![hidden_doc(), parse(&bye)]),
+                ("ntp".into(), vec![parse(&servers), parse(&omitted)]),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    fn host_attrs() -> Attrs {
+        Attrs::from(HashMap::<String, String>::from([("fqdn".into(), "host.example.com".into())]))
+    }
+
+
+    #[test]
+    fn test_hidden_values() {
+        let doc = hidden_doc();
+        let info = serde_json::to_value(DocumentInfo::from(&doc)).unwrap();
+        assert_eq!(info["default_value"], json!(HIDDEN_VALUE_MASK));
+        let overrides = serde_json::to_value(DocumentOverrides::from(&doc)).unwrap();
+        assert_eq!(overrides["fqdn=host.example.com"]["value"], json!(HIDDEN_VALUE_MASK));
+
+        let info = serde_json::to_value(DocumentInfo::from((&doc, Visibility::Revealed))).unwrap();
+        assert_eq!(info["default_value"], json!("secret"));
+        assert_eq!(Visibility::Masked.show(&doc, &json!(null)), json!(null));
+    }
+
+    #[test]
+    fn test_lookup_mode() {
+        let doc = hidden_doc();
+        let attrs = Attrs::from(HashMap::<String, String>::from([("domain".into(), "example.com".into())]));
+        assert!(LookupMode::Lenient.check(iter::once(&doc), &attrs).is_ok());
+        let report = LookupMode::Strict.check(iter::once(&doc), &attrs).expect_err("lookup is not rejected");
+        let report = serde_json::to_value(report).unwrap();
+        assert_eq!(report, json!({"missing": ["fqdn"], "ignored": ["domain"]}));
+        let attrs = Attrs::from(HashMap::<String, String>::from([("fqdn".into(), "host.example.com".into())]));
+        assert!(LookupMode::Strict.check(iter::once(&doc), &attrs).is_ok());
+
+        let disabled = Document::try_from(DOC_YAML).expect("could not parse document");
+        let attrs = Attrs::from(HashMap::<String, String>::from([("domain".into(), "example.com".into())]));
+        let report = LookupMode::Strict.check([&doc, &disabled], &attrs).expect_err("lookup is not rejected");
+        assert_eq!(serde_json::to_value(report).unwrap(), json!({"missing": ["fqdn"], "ignored": ["domain"]}));
+        assert!(LookupMode::Strict.check(iter::once(&disabled), &attrs).is_ok());
+    }
+
+    #[test]
+    fn test_node_classification() {
+        let node = NodeClassification::try_from((&collection(), &host_attrs(), Visibility::Revealed))
+            .unwrap_or_else(|err| panic!("could not classify node: {}", err));
+        let yaml: serde_yaml::Value = serde_yaml::from_str(&node.to_yaml().unwrap()).expect("ENC is not YAML");
+        let expected: serde_yaml::Value = serde_yaml::from_str(r#"
+        classes:
+          ntp:
+            servers: default
+          world:
+            bye: host-bye
+            hello: host-secret
+        "#).unwrap();
+        assert_eq!(yaml, expected);
+
+        // a masked value is never given to Puppet
+        match NodeClassification::try_from((&collection(), &host_attrs(), Visibility::Masked)) {
+            Err(CollectionError::HiddenValues(names)) => assert_eq!(names, vec!["world/hello"]),
+            _ => panic!("hidden values are not reported"),
+        }
+        let err = CollectionError::HiddenValues(vec!["world/hello".into()]);
+        assert_eq!(CollectionResponse::from(err).into_response().status(), StatusCode::FORBIDDEN);
+        let mut collection = collection();
+        collection.documents.get_mut("world").unwrap().retain(|doc| ! doc.is_hidden());
+        assert!(NodeClassification::try_from((&collection, &host_attrs(), Visibility::Masked)).is_ok());
+    }
+
+    #[test]
+    fn test_collections_values() {
+        let names = vec!["World".to_string()];
+        let values = CollectionsValues::try_from((&collection(), Some(&names), &host_attrs(), Visibility::Revealed))
+            .expect("could not look up values");
+        assert_eq!(serde_json::to_value(values).unwrap(), json!({"world": {"hello": "host-secret", "bye": "host-bye"}}));
+
+        let names = vec!["world".to_string(), "unknown".to_string()];
+        let err = CollectionsValues::try_from((&collection(), Some(&names), &host_attrs(), Visibility::Masked))
+            .err().expect("unknown collection is not reported");
+        assert!(matches!(&err, CollectionError::CollectionNotFound(name) if name == "unknown"));
+        assert_eq!(CollectionResponse::from(err).into_response().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_documents_values() {
+        let names = vec!["bye".to_string(), " Missing ".to_string()];
+        let values = DocumentsValues::try_from((&collection(), &"world".to_string(), names.as_slice(), &host_attrs(), Visibility::Masked))
+            .expect("could not look up values");
+        let values = serde_json::to_value(values).unwrap();
+        assert_eq!(values["unknown"], json!(["missing"]));
+        assert_eq!(values["values"].as_object().map(|it| it.len()), Some(1));
+        assert_eq!(values["values"]["bye"]["value"], json!("host-bye"));
+        assert!(DocumentsValues::try_from((&collection(), &"unknown".to_string(), names.as_slice(), &host_attrs(), Visibility::Masked)).is_err());
+    }
+
+    #[test]
+    fn test_batch_lookup() {
+        let batch: BatchLookup = serde_json::from_value(json!({
+            "attrs": [{"fqdn": "host.example.com"}, {"fqdn": "other.example.com"}],
+            "collections": ["ntp"],
+        })).unwrap();
+        let collection = collection();
+        let resolved = batch.resolve(&collection, LookupMode::Strict).unwrap_or_else(|_| panic!("batch is rejected"));
+        let values = BatchValues::try_from((&collection, &batch, resolved.as_slice(), Visibility::Masked))
+            .expect("could not look up values");
+        let values = serde_json::to_value(values).unwrap();
+        assert_eq!(values[0]["values"], json!({"ntp": {"servers": "default"}}));
+        assert_eq!(values[1]["values"], json!({"ntp": {"servers": "other", "pool": "default"}}));
+
+        let batch: BatchLookup = serde_json::from_value(json!({"attrs": vec![json!({"fqdn": "a"}); MAX_BATCH + 1]})).unwrap();
+        match batch.resolve(&collection, LookupMode::Lenient) {
+            Err(response) => assert_eq!(response.into_response().status(), StatusCode::BAD_REQUEST),
+            Ok(_) => panic!("a too large batch is not rejected"),
         }
     }
 }
@@ -0,0 +1,241 @@
+///
+///
+/// The module provides a pipeline which prepares lookup attributes before overrides are matched.
+///
+/// The pipeline is declared in the collection config file and runs in order:
+/// 1. normalization of values, i.e. lowercasing and canonical booleans (`yes`, `On` => `true`)
+/// 2. aliases, an attribute given under an alias is renamed, i.e. `host` => `fqdn`,
+///    the alias is dropped if the attribute is given as well
+/// 3. derived attributes, i.e. `domain` and `hostname` from `fqdn` or `subnet` from `ip`
+///
+/// ```yaml
+/// pipeline:
+///   normalize:
+///     lowercase: true
+///     booleans: true
+///   aliases:
+///     host: fqdn
+///   derive:
+///     - name: domain
+///       from: fqdn
+///       rule: domain
+///     - name: subnet
+///       from: ip
+///       rule: subnet
+///       prefix: 24
+/// ```
+///
+/// Attributes given by a caller are never overwritten by aliases or derived attributes.
+///
+///
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, net::IpAddr};
+use super::attrs::{Attrs, DELIMITERS};
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Normalize {
+    /// lowercase values
+    pub lowercase: bool,
+    /// turn `yes/no`, `on/off`, `y/n` and `true/false` in any case into `true/false`
+    pub booleans: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeriveRule {
+    /// `host.example.com` => `example.com`
+    Domain,
+    /// `host.example.com` => `host`
+    Hostname,
+    /// `10.1.2.3` => `10.1.2.0/24`, the prefix is 24 for IPv4 and 64 for IPv6 if not given
+    Subnet,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DerivedAttr {
+    /// a name of the derived attribute
+    pub name: String,
+    /// an attribute the value is derived from
+    pub from: String,
+    pub rule: DeriveRule,
+    /// a prefix length of the `subnet` rule
+    #[serde(default)]
+    pub prefix: Option<u8>,
+}
+
+impl DerivedAttr {
+    fn derive(&self, value: &str) -> Option<String> {
+        match self.rule {
+            DeriveRule::Domain => value.split_once('.')
+                .map(|(_, domain)| domain.to_string())
+                .filter(|it| ! it.is_empty()),
+            DeriveRule::Hostname => value.split('.').next()
+                .map(String::from)
+                .filter(|it| ! it.is_empty()),
+            DeriveRule::Subnet => subnet_of(value, self.prefix),
+        }
+    }
+}
+
+///
+/// Get a network address with a prefix length of an IP address, i.e. `10.1.2.0/24`.
+///
+fn subnet_of(value: &str, prefix: Option<u8>) -> Option<String> {
+    let addr = value.split('/').next()?.parse::<IpAddr>().ok()?;
+    match addr {
+        IpAddr::V4(addr) => {
+            let prefix = prefix.unwrap_or(24).min(32);
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            Some(format!("{}/{}", std::net::Ipv4Addr::from(u32::from(addr) & mask), prefix))
+        },
+        IpAddr::V6(addr) => {
+            let prefix = prefix.unwrap_or(64).min(128);
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            Some(format!("{}/{}", std::net::Ipv6Addr::from(u128::from(addr) & mask), prefix))
+        },
+    }
+}
+
+fn canonical_boolean(value: &str) -> Option<&'static str> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "y" | "on" => Some("true"),
+        "false" | "no" | "n" | "off" => Some("false"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttrsPipeline {
+    pub normalize: Normalize,
+    /// an alias and a name of the attribute it stands for
+    pub aliases: BTreeMap<String, String>,
+    /// attributes derived in the listed order, so a derived attribute may be derived from another one
+    pub derive: Vec<DerivedAttr>,
+}
+
+impl AttrsPipeline {
+    ///
+    /// Check names of aliases and derived attributes.
+    ///
+    pub fn validate(&self) -> Result<(), String> {
+        let check = |name: &str| match name.trim().is_empty() || name.contains(DELIMITERS) || name != name.to_lowercase() {
+            true => Err(format!("bad attribute name {:?} in the pipeline", name)),
+            false => Ok(()),
+        };
+        for (alias, attr) in self.aliases.iter() {
+            check(alias)?;
+            check(attr)?;
+            if alias == attr {
+                return Err(format!("attribute {} is an alias of itself", alias));
+            }
+        }
+        for derived in self.derive.iter() {
+            check(&derived.name)?;
+            check(&derived.from)?;
+            if derived.prefix.is_some() && derived.rule != DeriveRule::Subnet {
+                return Err(format!("prefix is only used by the subnet rule of attribute {}", derived.name));
+            }
+            if derived.prefix.is_some_and(|it| it > 128) {
+                return Err(format!("bad prefix length of attribute {}", derived.name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    ///
+    /// Run the pipeline on `attrs`.
+    ///
+    pub fn apply(&self, attrs: &Attrs) -> Attrs {
+        let mut attrs = attrs.clone();
+        if self.normalize.lowercase {
+            attrs.map_values(|value| value.to_lowercase());
+        }
+        if self.normalize.booleans {
+            attrs.map_values(|value| canonical_boolean(value).map_or_else(|| value.to_string(), String::from));
+        }
+        for (alias, attr) in self.aliases.iter() {
+            match attrs.remove(alias) {
+                Some(values) if ! attrs.contains(attr) => attrs.insert_values(attr, values),
+                _ => (),
+            }
+        }
+        for derived in self.derive.iter() {
+            if attrs.contains(&derived.name) {
+                continue;
+            }
+            let values = attrs.get(&derived.from)
+                .map(|values| values.iter().filter_map(|it| derived.derive(it)).collect::<Vec<String>>())
+                .unwrap_or_default();
+            if ! values.is_empty() {
+                attrs.insert_values(&derived.name, values);
+            }
+        }
+        attrs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use super::{Attrs, AttrsPipeline};
+
+    const PIPELINE_YAML: &str = r#"
+    normalize:
+      lowercase: true
+      booleans: true
+    aliases:
+      host: fqdn
+    derive:
+      - name: domain
+        from: fqdn
+        rule: domain
+      - name: hostname
+        from: fqdn
+        rule: hostname
+      - name: subnet
+        from: ip
+        rule: subnet
+        prefix: 20
+    "#;
+
+    #[test]
+    fn test_pipeline() {
+        let pipeline: AttrsPipeline = serde_yaml::from_str(PIPELINE_YAML).expect("could not parse pipeline");
+        pipeline.validate().expect("pipeline is not valid");
+        let attrs = Attrs::from(HashMap::<String, String>::from([
+            ("host".into(), "Web1.Example.com".into()),
+            ("is_virtual".into(), "Yes".into()),
+            ("ip".into(), "10.1.18.7".into()),
+        ]));
+        let attrs = pipeline.apply(&attrs);
+        assert_eq!(attrs.get("host"), None);
+        assert_eq!(attrs.values("fqdn"), vec!["web1.example.com"]);
+        assert_eq!(attrs.values("domain"), vec!["example.com"]);
+        assert_eq!(attrs.values("hostname"), vec!["web1"]);
+        assert_eq!(attrs.values("is_virtual"), vec!["true"]);
+        assert_eq!(attrs.values("subnet"), vec!["10.1.16.0/20"]);
+
+        // attributes given by a caller win
+        let attrs = Attrs::from(HashMap::<String, String>::from([
+            ("host".into(), "web1.example.com".into()),
+            ("fqdn".into(), "db1.example.com".into()),
+            ("domain".into(), "example.org".into()),
+        ]));
+        let attrs = pipeline.apply(&attrs);
+        assert_eq!(attrs.get("host"), None);
+        assert_eq!(attrs.values("fqdn"), vec!["db1.example.com"]);
+        assert_eq!(attrs.values("domain"), vec!["example.org"]);
+        assert_eq!(attrs.values("hostname"), vec!["db1"]);
+        assert_eq!(attrs.get("subnet"), None);
+
+        let pipeline: AttrsPipeline = serde_yaml::from_str("aliases: {fqdn: fqdn}").unwrap();
+        assert!(pipeline.validate().is_err());
+    }
+}
@@ -0,0 +1,211 @@
+///
+///
+/// The module provides reloading of a collection directory while the server is running.
+///
+/// A new `Collection` is loaded in a blocking task and swapped into `SharedCollection`
+/// under a write lock, so lookups see either the old or the new tree but never a mix of them.
+/// The current tree is kept if the new one could not be loaded.
+///
+/// A reload is triggered by changes of files (`--watch`), `POST /admin/reload` or `SIGHUP`.
+///
+///
+use std::{path::PathBuf, sync::atomic::{AtomicBool, Ordering}, time::{Duration, Instant}};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{mpsc, Mutex};
+use super::{Collection, CollectionError, SharedCollection, collection::CONFIG_FILE, models::{LoadFailure, ReloadSummary}};
+
+///
+/// Reloads of one collection. Reloads are run one by one, so an older tree never replaces a newer one.
+///
+#[derive(Debug, Default)]
+pub struct ReloadState {
+    lock: Mutex<()>,
+    /// set while a reload waits for the running one
+    queued: AtomicBool,
+}
+
+///
+/// Clears `ReloadState::queued` when dropped, so a waiting reload which is cancelled,
+/// i.e. a client of `POST /admin/reload` went away, does not leave the flag set.
+///
+struct QueuedGuard<'a>(&'a AtomicBool);
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+///
+/// Load a collection again from the directory and with the options it was loaded with
+/// and swap it into `shared`.
+/// At most one reload waits for the running one. A reload requested meanwhile is not run
+/// and gets `ReloadSummary::queued`, as the waiting reload loads the directory after it.
+///
+pub async fn reload(shared: &SharedCollection) -> Result<ReloadSummary, CollectionError> {
+    let state = &shared.3;
+    if state.queued.swap(true, Ordering::SeqCst) {
+        return Ok(ReloadSummary::queued());
+    }
+    let queued = QueuedGuard(&state.queued);
+    let _guard = state.lock.lock().await;
+    drop(queued);
+    let start_time = Instant::now();
+    let (path, options) = {
+        let current = shared.0.read().await;
+        (current.path.clone(), current.options.clone())
+    };
+    let loaded = tokio::task::spawn_blocking(move || Collection::try_from((&path, &options)))
+        .await
+        .map_err(|err| CollectionError::LoadError(err.to_string()))
+        .and_then(|it| it);
+    let collection = match loaded {
+        Ok(collection) => collection,
+        Err(err) => {
+            // kept for the load report until a reload succeeds
+            *shared.2.write().await = Some(LoadFailure::from(&err));
+            return Err(err);
+        },
+    };
+    tracing::info!(
+        "reloaded {} documents from {} collections in {:?}, {} files at {:.0} files/s",
+        collection.total_documents(), collection.total_collections(), &start_time.elapsed(),
+        collection.report.total_files, collection.report.files_per_sec()
+    );
+    let mut current = shared.0.write().await;
+    let summary = ReloadSummary::from((&*current, &collection));
+    *current = collection;
+    *shared.2.write().await = None;
+    Ok(summary)
+}
+
+///
+/// Reload a collection and log the outcome, a failed reload keeps the current collection.
+///
+async fn reload_logged(shared: &SharedCollection, reason: &str) {
+    tracing::info!("{}, reloading collection ...", reason);
+    match reload(shared).await {
+        Ok(summary) if summary.is_queued() => tracing::info!("a reload is queued already"),
+        Ok(summary) => tracing::info!("collection reloaded {:?}", &summary),
+        Err(err) => tracing::error!("could not reload collection, keeping the current one: {}", err),
+    }
+}
+
+///
+/// Reload `shared` on `SIGHUP`.
+///
+#[cfg(unix)]
+pub fn reload_on_hangup(shared: SharedCollection) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            reload_logged(&shared, "SIGHUP received").await;
+        }
+    });
+    Ok(())
+}
+
+///
+/// Whether an event changes documents or the config of a collection.
+///
+fn is_relevant(event: &Event) -> bool {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+    event.paths.iter().any(|path| {
+        let name = path.file_name().map(|it| it.to_string_lossy()).unwrap_or_default();
+        name == CONFIG_FILE || name.ends_with(".yml") || name.ends_with(".yaml") || path.is_dir()
+    })
+}
+
+///
+/// Watch the collection directory `path` and reload it into `shared` once no changes
+/// come within `delay`. The returned watcher must be kept alive for watching to go on.
+///
+pub fn watch(shared: SharedCollection, path: PathBuf, delay: Duration) -> Result<RecommendedWatcher, notify::Error> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+        Ok(event) if is_relevant(&event) => { let _ = tx.send(()); },
+        Ok(_) => (),
+        Err(err) => tracing::error!("watching collection failed: {}", err),
+    })?;
+    watcher.watch(&path, RecursiveMode::Recursive)?;
+    tracing::info!("watching {:?} for changes", &path);
+    tokio::spawn(async move {
+        while rx.recv().await.is_some() {
+            // debounce, editors and deploy tools change many files at once
+            loop {
+                match tokio::time::timeout(delay, rx.recv()).await {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => return,
+                    Err(_) => break,
+                }
+            }
+            reload_logged(&shared, &format!("{:?} changed", &path)).await;
+        }
+    });
+    Ok(watcher)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::atomic::Ordering, time::Duration};
+    use super::{reload, Collection, ReloadSummary, SharedCollection};
+    use crate::collection::{models::LoadAttempt, testing::DOC_YAML};
+    use crate::collection::CollectionOptions;
+
+
+    #[tokio::test]
+    async fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_path_buf();
+        std::fs::write(path.join("hello.yaml"), DOC_YAML).unwrap();
+        let collection = Collection::try_from((&path, &CollectionOptions::default())).expect("could not load collection");
+        let shared = SharedCollection::from(collection);
+
+        std::fs::write(path.join("hello.yaml"), DOC_YAML.replace("default_value: default", "default_value: changed")).unwrap();
+        std::fs::write(path.join("bye.yaml"), DOC_YAML.replace("parameter: hello", "parameter: bye")).unwrap();
+        let summary = serde_json::to_value(reload(&shared).await.expect("could not reload collection")).unwrap();
+        assert_eq!(summary["added"], serde_json::json!(["world/bye"]));
+        assert_eq!(summary["modified"], serde_json::json!(["world/hello"]));
+        assert_eq!(shared.0.read().await.total_documents(), 2);
+
+        // a reload requested while another one waits for the running one is not run
+        let running = shared.3.lock.lock().await;
+        let waiting = tokio::spawn({
+            let shared = shared.clone();
+            async move { reload(&shared).await }
+        });
+        while !shared.3.queued.load(Ordering::SeqCst) {
+            tokio::task::yield_now().await;
+        }
+        assert!(reload(&shared).await.unwrap().is_queued());
+        drop(running);
+        assert!(waiting.await.unwrap().unwrap().is_reloaded());
+
+        // a waiting reload which is cancelled does not keep later ones queued
+        let running = shared.3.lock.lock().await;
+        assert!(tokio::time::timeout(Duration::from_millis(20), reload(&shared)).await.is_err());
+        drop(running);
+        assert!(reload(&shared).await.unwrap().is_reloaded());
+
+        // a broken tree keeps the current collection
+        std::fs::write(path.join("bad.yaml"), "parameter: [").unwrap();
+        let err = reload(&shared).await.expect_err("bad document is not reported");
+        let summary = serde_json::to_value(ReloadSummary::from(&err)).unwrap();
+        assert_eq!(summary["bad_document"]["path"], serde_json::json!(path.join("bad.yaml")));
+        assert!(summary["errors"][0].as_str().is_some_and(|it| it.starts_with("bad document ")));
+        // the load report tells about the failure until a reload succeeds
+        let attempt = |shared: &SharedCollection| {
+            let report = shared.0.try_read().unwrap().report.clone();
+            serde_json::to_value(LoadAttempt::from((report, shared.2.try_read().unwrap().clone()))).unwrap()
+        };
+        assert_eq!(attempt(&shared)["status"], "failed");
+        assert_eq!(attempt(&shared)["current"]["total_documents"], 2);
+        assert_eq!(shared.0.read().await.total_documents(), 2);
+        std::fs::remove_file(path.join("bad.yaml")).unwrap();
+        assert!(reload(&shared).await.is_ok());
+        assert_eq!(attempt(&shared)["status"], "loaded");
+    }
+}
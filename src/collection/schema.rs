@@ -0,0 +1,251 @@
+///
+///
+/// The module provides a schema of attributes known to a collection.
+///
+/// The schema is declared in the collection config file:
+///
+/// ```yaml
+/// schema:
+///   - name: fqdn
+///     pattern: '^[a-z0-9.-]+$'
+///     description: a fully qualified domain name of a host
+///   - name: env
+///     values: [prod, stage, dev]
+///   - name: os_release
+///     type: number
+///   - name: is_virtual
+///     type: boolean
+/// ```
+///
+/// If a schema is declared, override orders and override keys of documents may only use
+/// attributes of the schema and lookups with unknown or malformed attributes are rejected.
+/// Values are checked case insensitively, empty values mean missing attributes and are not checked.
+///
+///
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use super::{
+    attrs::{Attrs, DELIMITERS},
+    document::Document,
+    matcher::{Condition, Matcher},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttrType {
+    #[default]
+    String,
+    Number,
+    /// `true` or `false`
+    Boolean,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttrSchema {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub value_type: AttrType,
+    /// allowed values
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<String>>,
+    /// a regular expression values must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip)]
+    regex: Option<Regex>,
+}
+
+impl From<&str> for AttrSchema {
+    ///
+    /// An attribute which is not described by a schema.
+    ///
+    fn from(name: &str) -> Self {
+        Self { name: name.into(), value_type: AttrType::default(), values: None, pattern: None, description: None, regex: None }
+    }
+}
+
+impl AttrSchema {
+    ///
+    /// Check a value of the attribute.
+    ///
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        let value = value.trim().to_lowercase();
+        if value.is_empty() {
+            return Ok(());
+        }
+        let error = |reason: &str| Err(format!("attribute {} has a value {:?} {}", &self.name, &value, reason));
+        match self.value_type {
+            AttrType::Number if value.parse::<f64>().is_err() => return error("which is not a number"),
+            AttrType::Boolean if value != "true" && value != "false" => return error("which is not a boolean"),
+            _ => (),
+        }
+        if let Some(values) = &self.values {
+            if ! values.iter().any(|it| it.to_lowercase() == value) {
+                return error(&format!("which is not one of {:?}", values));
+            }
+        }
+        if let Some(regex) = &self.regex {
+            if ! regex.is_match(&value) {
+                return error(&format!("which does not match {}", regex.as_str()));
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Check a condition of an override key. Only literal values are checked,
+    /// wildcards and regular expressions are left as is.
+    ///
+    fn check_condition(&self, condition: &Condition) -> Result<(), String> {
+        match condition {
+            Condition::Equal(value) => self.check(value),
+            Condition::In(values) => values.iter().try_for_each(|value| self.check(value)),
+            Condition::Compare(_, value) if self.value_type == AttrType::Number => match value.parse::<f64>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(format!("attribute {} is compared with {:?} which is not a number", &self.name, value)),
+            },
+            Condition::Not(condition) => self.check_condition(condition),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(try_from = "Vec<AttrSchema>")]
+pub struct Schema(Vec<AttrSchema>);
+
+impl TryFrom<Vec<AttrSchema>> for Schema {
+    type Error = String;
+    ///
+    /// Lowercase names of attributes and compile their patterns.
+    ///
+    fn try_from(items: Vec<AttrSchema>) -> Result<Self, Self::Error> {
+        let mut this = Self(Vec::with_capacity(items.len()));
+        for mut item in items {
+            item.name = item.name.trim().to_lowercase();
+            if item.name.is_empty() || item.name.contains(DELIMITERS) {
+                return Err(format!("bad attribute name {:?} in the schema", &item.name));
+            }
+            if this.get(&item.name).is_some() {
+                return Err(format!("attribute {} is declared twice in the schema", &item.name));
+            }
+            if let Some(pattern) = &item.pattern {
+                item.regex = Some(RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|err| format!("bad pattern of attribute {}: {}", &item.name, err))?);
+            }
+            this.0.push(item);
+        }
+        Ok(this)
+    }
+}
+
+impl Schema {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AttrSchema> {
+        self.0.iter().find(|it| it.name == name)
+    }
+
+    fn known(&self, name: &str) -> Result<&AttrSchema, String> {
+        self.get(name).ok_or_else(|| format!("attribute {} is not declared in the schema", name))
+    }
+
+    ///
+    /// Check that `attrs` of a lookup are known and well formed, any attributes pass if the schema is empty.
+    ///
+    pub fn validate_attrs(&self, attrs: &Attrs) -> Result<(), String> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        for name in attrs.names() {
+            let schema = self.known(name)?;
+            attrs.values(name).into_iter().try_for_each(|value| schema.check(value))?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Check that override orders and override keys of a `document` only use known attributes
+    /// and compare them with well formed values.
+    ///
+    pub fn validate_document(&self, document: &Document) -> Result<(), String> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let error = |err: String| format!("document {}/{}: {}", &document.collection, &document.name, err);
+        for attr in document.override_attrs() {
+            self.known(&attr).map_err(error)?;
+        }
+        for key in document.overrides.keys() {
+            let matcher = Matcher::try_from(key.as_str()).map_err(error)?;
+            for (attr, condition) in matcher.conditions() {
+                self.known(attr)
+                    .and_then(|schema| schema.check_condition(condition))
+                    .map_err(|err| error(format!("override {}: {}", key, err)))?;
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Get schemas of attributes `names`, attributes missing in the schema get a default one.
+    ///
+    pub fn describe(&self, names: &[String]) -> Vec<AttrSchema> {
+        names.iter()
+            .map(|name| self.get(name).cloned().unwrap_or_else(|| AttrSchema::from(name.as_str())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use super::{Attrs, Document, Schema};
+    use crate::collection::testing::doc_yaml;
+
+    const SCHEMA_YAML: &str = r#"
+    - name: fqdn
+      pattern: '^[a-z0-9.-]+$'
+      description: a fully qualified domain name
+    - name: Env
+      values: [prod, stage]
+    - name: os_release
+      type: number
+    "#;
+
+
+    #[test]
+    fn test_schema() {
+        let schema: Schema = serde_yaml::from_str(SCHEMA_YAML).expect("could not parse schema");
+        let attrs = |items: &[(&str, &str)]| Attrs::from(items.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<String, String>>());
+        assert!(schema.validate_attrs(&attrs(&[("fqdn", "Host.example.com"), ("env", "PROD"), ("os_release", "8.1")])).is_ok());
+        assert!(schema.validate_attrs(&attrs(&[("env", "")])).is_ok());
+        assert!(schema.validate_attrs(&attrs(&[("unknown", "1")])).is_err());
+        assert!(schema.validate_attrs(&attrs(&[("env", "dev")])).is_err());
+        assert!(schema.validate_attrs(&attrs(&[("fqdn", "host_1")])).is_err());
+        assert!(schema.validate_attrs(&attrs(&[("os_release", "eight")])).is_err());
+
+        let doc_yaml = doc_yaml(
+            &[("fqdn=host.example.com", "host"), ("env in (prod, stage),os_release>=8", "env")],
+            &["fqdn", "env,os_release"],
+        );
+        let doc = Document::try_from(doc_yaml.as_str()).expect("could not parse document");
+        assert!(schema.validate_document(&doc).is_ok());
+        let doc = Document::try_from(doc_yaml.replace("prod, stage", "prod, dev").as_str()).unwrap();
+        assert!(schema.validate_document(&doc).is_err());
+        let doc = Document::try_from(doc_yaml.replace("- fqdn\n", "- hostname\n").as_str()).unwrap();
+        assert!(schema.validate_document(&doc).is_err());
+
+        assert!(serde_yaml::from_str::<Schema>("[{name: a}, {name: A}]").is_err());
+        assert!(serde_yaml::from_str::<Schema>("[{name: a, pattern: '('}]").is_err());
+    }
+}
@@ -0,0 +1,38 @@
+///
+/// A document `world/hello` with the default value `default` and an override of `fqdn=host.example.com`
+/// with the value `overridden`, overrides of the document are disabled.
+///
+pub const DOC_YAML: &str = r#"
+description: Test document
+default_value: default
+override: false
+parameter_type: string
+parameter: hello
+puppetclass_name: world
+omit: false
+merge_default: false
+merge_overrides: false
+override_values:
+  - match: fqdn=host.example.com
+    omit: false
+    value: overridden
+override_value_order:
+  - fqdn
+hidden_value: false
+validator_rule: null
+validator_type: null
+"#;
+
+///
+/// Get a document like `DOC_YAML` with enabled overrides `(match, value)` and an override order `order`.
+///
+pub fn doc_yaml(overrides: &[(&str, &str)], order: &[&str]) -> String {
+    let overrides = overrides.iter()
+        .map(|(key, value)| format!("  - match: {}\n    omit: false\n    value: {}\n", key, value))
+        .collect::<String>();
+    let order = order.iter().map(|it| format!("  - {}\n", it)).collect::<String>();
+    DOC_YAML
+        .replace("override: false", "override: true")
+        .replace("  - match: fqdn=host.example.com\n    omit: false\n    value: overridden\n", &overrides)
+        .replace("  - fqdn\n", &order)
+}
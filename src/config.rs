@@ -4,6 +4,7 @@ use std::path::{PathBuf, Path};
 use std::env;
 use std::convert::Into;
 use tracing::Level;
+use crate::collection::{ApiSettings, CollectionOptions, DELIMITERS, MergeStrategy as DocumentMergeStrategy};
 
 #[derive(clap::ValueEnum, Default, Debug, Clone)]
 pub enum LogLevel {
@@ -15,9 +16,9 @@ pub enum LogLevel {
     Error,
 }
 
-impl Into<String> for LogLevel {
-    fn into(self) -> String {
-        let level = match self {
+impl From<LogLevel> for String {
+    fn from(val: LogLevel) -> Self {
+        let level = match val {
             LogLevel::Info => "info",
             LogLevel::Warn => "warn",
             LogLevel::Debug => "debug",
@@ -28,9 +29,9 @@ impl Into<String> for LogLevel {
     }
 }
 
-impl Into<Level> for LogLevel {
-    fn into(self) -> Level {
-        match self {
+impl From<LogLevel> for Level {
+    fn from(val: LogLevel) -> Self {
+        match val {
             LogLevel::Info => Level::INFO,
             LogLevel::Warn => Level::WARN,
             LogLevel::Debug => Level::DEBUG,
@@ -40,6 +41,24 @@ impl Into<Level> for LogLevel {
     }
 }
 
+#[derive(clap::ValueEnum, Default, Debug, Clone)]
+pub enum MergeStrategy {
+    First,
+    Hash,
+    #[default]
+    Deep,
+}
+
+impl From<MergeStrategy> for DocumentMergeStrategy {
+    fn from(val: MergeStrategy) -> Self {
+        match val {
+            MergeStrategy::First => DocumentMergeStrategy::First,
+            MergeStrategy::Hash => DocumentMergeStrategy::Hash,
+            MergeStrategy::Deep => DocumentMergeStrategy::Deep,
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct CliArgs {
@@ -58,12 +77,82 @@ pub struct CliArgs {
     /// Ignore bad documents. If true it will fail if any document incorrect
     #[arg(short, long, default_value_t = false)]
     pub ignore_bad_documents: bool,
+    /// Keep the first of documents declared in many files and the first of overrides with the same keys,
+    /// otherwise duplicates are bad documents
+    #[arg(long, default_value_t = false)]
+    pub ignore_duplicates: bool,
+    /// Merge strategy of documents with `merge_overrides` set and no `merge_strategy` given
+    #[arg(value_enum, short, long, default_value_t = MergeStrategy::default())]
+    pub merge_strategy: MergeStrategy,
+    /// Attributes which values are paths like `base/web/prod`, i.e. `hostgroup,location`,
+    /// an override of an ancestor applies to all its descendants. None by default
+    #[arg(long, value_delimiter = ',')]
+    pub hierarchical_attrs: Vec<String>,
+    /// Workers parsing documents while loading the collection, all available CPUs are used if 0
+    #[arg(long, default_value_t = 0)]
+    pub load_threads: usize,
+    /// A token to reveal hidden values with `Authorization: Bearer <token>`, hidden values are always masked if not set
+    #[arg(long, env = "TAKEIT_REVEAL_TOKEN", hide_env_values = true)]
+    pub reveal_token: Option<String>,
+    /// A token to use the admin API with `Authorization: Bearer <token>`, the admin API is closed if not set
+    #[arg(long, env = "TAKEIT_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+    /// Reject lookups missing attributes used by overrides, a request may turn it off with `X-Strict-Lookup: false`
+    #[arg(long, default_value_t = false)]
+    pub strict_lookup: bool,
+    /// Reload the collection directory when its files change
+    #[arg(short, long, default_value_t = false)]
+    pub watch: bool,
+    /// Milliseconds to wait for more changes before the collection directory is reloaded
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    pub watch_delay: u64,
+    /// Print a Puppet External Node Classifier document for a host with the given fqdn and exit
+    #[arg(long, value_name = "FQDN")]
+    pub enc: Option<String>,
+    /// Attributes of the host for `--enc` given as key=value, may be repeated
+    #[arg(short, long = "attr", value_name = "KEY=VALUE", value_parser = parse_attr)]
+    pub attrs: Vec<(String, String)>,
+}
+
+///
+/// Parse a `key=value` attribute, keys and values must not contain `DELIMITERS`.
+///
+fn parse_attr(value: &str) -> Result<(String, String), String> {
+    let (key, attr_value) = value.split_once('=')
+        .map(|(key, value)| (key.trim().to_lowercase(), value.trim().to_string()))
+        .filter(|(key, _)| ! key.is_empty())
+        .ok_or_else(|| format!("attribute {} is not a key=value pair", value))?;
+    match key.contains(DELIMITERS) || attr_value.contains(DELIMITERS) {
+        true => Err(format!("attribute {} must not contain any of {:?}", value, DELIMITERS)),
+        false => Ok((key, attr_value)),
+    }
 }
 
 impl CliArgs {
     pub fn log_level_as_str(&self) -> String {
         self.log_level.clone().into()
     }
+
+    pub fn collection_options(&self) -> CollectionOptions {
+        CollectionOptions {
+            ignore_bad_documents: self.ignore_bad_documents,
+            ignore_duplicates: self.ignore_duplicates,
+            merge_strategy: self.merge_strategy.clone().into(),
+            hierarchical_attrs: self.hierarchical_attrs.iter()
+                .map(|it| it.trim().to_lowercase())
+                .filter(|it| ! it.is_empty())
+                .collect(),
+            load_threads: self.load_threads,
+        }
+    }
+
+    pub fn api_settings(&self) -> ApiSettings {
+        ApiSettings {
+            reveal_token: self.reveal_token.clone().filter(|it| ! it.is_empty()),
+            strict_lookup: self.strict_lookup,
+            admin_token: self.admin_token.clone().filter(|it| ! it.is_empty()),
+        }
+    }
 }
 
 
@@ -80,3 +169,25 @@ fn default_storedir() -> PathBuf {
 pub fn cli_args() -> CliArgs {
     CliArgs::parse()
 }
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+    use super::{parse_attr, CliArgs};
+
+    #[test]
+    fn test_parse_attr() {
+        assert_eq!(parse_attr(" Env = prod "), Ok(("env".into(), "prod".into())));
+        assert!(parse_attr("env").is_err());
+        assert!(parse_attr("env=prod,stage").is_err());
+        assert!(parse_attr("env=a=b").is_err());
+    }
+
+    #[test]
+    fn test_hierarchical_attrs() {
+        let args = CliArgs::parse_from(["takeit", "-c", "docs"]);
+        assert!(args.collection_options().hierarchical_attrs.is_empty());
+        let args = CliArgs::parse_from(["takeit", "-c", "docs", "--hierarchical-attrs", "HostGroup, location"]);
+        assert_eq!(args.collection_options().hierarchical_attrs, vec!["hostgroup", "location"]);
+    }
+}
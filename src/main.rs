@@ -12,15 +12,14 @@ use config::LogLevel;
 
 fn init_logger(level: Level) {
     fn configure(level: Level) {
-        match tracing_subscriber::fmt()
+        if let Err(err) = tracing_subscriber::fmt()
             .with_writer(std::io::stderr)
             .with_max_level(level)
             .with_span_events(FmtSpan::CLOSE)
             .with_ansi(true)
             .try_init()
         {
-            Err(err) => panic!("configure logger error"),
-            _ => ()
+            panic!("configure logger error: {}", err);
         }
     }
     configure(level);
@@ -32,5 +31,9 @@ fn init_logger(level: Level) {
 async fn main() {
     let cli_args = config::cli_args();
     init_logger(cli_args.log_level.clone().into());
-    let _ = api::run_server(&cli_args).await.expect("failed to run server");
+    if let Some(fqdn) = &cli_args.enc {
+        api::print_node_classification(&cli_args, fqdn).expect("failed to classify node");
+        return;
+    }
+    api::run_server(&cli_args).await.expect("failed to run server");
 }